@@ -0,0 +1,356 @@
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use barkit_extract::error::Error;
+
+use crate::sample_sheet::{hamming_distance, ReadBarcodes, SampleSheet, UNDETERMINED};
+
+/// Max number of distinct index combinations with unmatched indices counted by their
+/// read sequences. Further combinations are counted as `undetermined`, so that sequencing
+/// errors in index reads do not exhaust the memory.
+const MAX_UNMATCHED_COMBINATIONS: usize = 10_000;
+
+/// Index combination of the hopping matrix: barcodes of the sample sheet for matched
+/// indices, read sequences for unmatched ones and empty labels for undetermined ones
+type Combination = (Vec<u8>, Vec<u8>);
+
+/// Assigns reads to samples by i7 and i5 index barcodes matched independently.
+/// Reads with both indices matched to a combination absent in the sample sheet
+/// (e.g. caused by index hopping) are not assigned. Counts of all observed
+/// index combinations are collected into the hopping matrix.
+pub struct DualIndexAssigner {
+    /// Distinct i7 barcodes of the sample sheet
+    i7_barcodes: Vec<Vec<u8>>,
+
+    /// Distinct i5 barcodes of the sample sheet
+    i5_barcodes: Vec<Vec<u8>>,
+
+    /// Indices of i7 and i5 barcodes of samples
+    samples: Vec<(usize, usize)>,
+
+    /// Max number of mismatches in each index barcode
+    max_mismatches: usize,
+
+    /// Counts of combinations of i7 and i5 barcodes of the sample sheet
+    hopping_matrix: Vec<AtomicUsize>,
+
+    /// Counts of combinations with unmatched indices (e.g. foreign indices of other
+    /// libraries on the lane)
+    unmatched_counts: Mutex<HashMap<Combination, usize>>,
+}
+
+impl DualIndexAssigner {
+    /// Creates `DualIndexAssigner` instance. Checks that every sample has both index barcodes
+    /// and barcodes of each index differ by more than twice the max mismatches, so a read
+    /// index can not be within the max mismatches of two barcodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_demux::dual_index::DualIndexAssigner;
+    /// use barkit_demux::sample_sheet::{ReadBarcodes, SampleSheet};
+    ///
+    /// let sample_sheet = SampleSheet::parse("sample,i7,i5\nA,AAAA,GGGG\nB,CCCC,TTTT").unwrap();
+    /// let assigner = DualIndexAssigner::new(&sample_sheet, 1).unwrap();
+    ///
+    /// // i7 of sample A and i5 of sample B are swapped by index hopping
+    /// let read_barcodes = ReadBarcodes { i7: Some(b"AAAT"), i5: Some(b"TTTT"), ..Default::default() };
+    /// assert_eq!(None, assigner.assign(&read_barcodes));
+    /// ```
+    pub fn new(sample_sheet: &SampleSheet, max_mismatches: usize) -> Result<Self, Error> {
+        let mut i7_barcodes: Vec<Vec<u8>> = Vec::new();
+        let mut i5_barcodes: Vec<Vec<u8>> = Vec::new();
+        let mut samples = Vec::new();
+
+        for sample in &sample_sheet.samples {
+            let (Some(i7), Some(i5), None) = (&sample.i7, &sample.i5, &sample.inline) else {
+                return Err(Error::InvalidSampleSheet(
+                    "dual-index demultiplexing expects i7 and i5 barcodes without inline ones"
+                        .to_owned(),
+                ));
+            };
+            let indices = (
+                get_or_insert(&mut i7_barcodes, i7),
+                get_or_insert(&mut i5_barcodes, i5),
+            );
+            if samples.contains(&indices) {
+                return Err(Error::InvalidSampleSheet(format!(
+                    "sample {} has the same i7 and i5 barcodes as another sample",
+                    sample.name
+                )));
+            }
+            samples.push(indices);
+        }
+
+        check_distances(&i7_barcodes, max_mismatches)?;
+        check_distances(&i5_barcodes, max_mismatches)?;
+
+        let hopping_matrix = (0..i7_barcodes.len() * i5_barcodes.len())
+            .map(|_| AtomicUsize::new(0))
+            .collect();
+
+        Ok(Self {
+            i7_barcodes,
+            i5_barcodes,
+            samples,
+            max_mismatches,
+            hopping_matrix,
+            unmatched_counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns index of the sample with both i7 and i5 barcodes within the max mismatches
+    /// of the read indices and counts the observed combination in the hopping matrix
+    pub fn assign(&self, read_barcodes: &ReadBarcodes) -> Option<usize> {
+        let i7 = self.match_index(&self.i7_barcodes, read_barcodes.i7);
+        let i5 = self.match_index(&self.i5_barcodes, read_barcodes.i5);
+
+        match (i7, i5) {
+            (Some(i7), Some(i5)) => {
+                self.hopping_matrix[i7 * self.i5_barcodes.len() + i5]
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            _ => self.count_unmatched(
+                get_label(&self.i7_barcodes, i7, read_barcodes.i7),
+                get_label(&self.i5_barcodes, i5, read_barcodes.i5),
+                (i7.is_some(), i5.is_some()),
+            ),
+        }
+
+        let indices = (i7?, i5?);
+        self.samples.iter().position(|sample| *sample == indices)
+    }
+
+    /// Counts the combination with unmatched indices. If the max number of combinations
+    /// is reached, sequences of unmatched indices of new combinations are counted as
+    /// undetermined ones.
+    fn count_unmatched(&self, i7: Vec<u8>, i5: Vec<u8>, matched: (bool, bool)) {
+        let mut unmatched_counts = self
+            .unmatched_counts
+            .lock()
+            .expect("Counts of unmatched indices are not poisoned");
+
+        let mut combination = (i7, i5);
+        if unmatched_counts.len() >= MAX_UNMATCHED_COMBINATIONS
+            && !unmatched_counts.contains_key(&combination)
+        {
+            if !matched.0 {
+                combination.0.clear();
+            }
+            if !matched.1 {
+                combination.1.clear();
+            }
+        }
+        *unmatched_counts.entry(combination).or_insert(0) += 1;
+    }
+
+    /// Returns index of the barcode within the max mismatches of the read index
+    fn match_index(&self, barcodes: &[Vec<u8>], read_index: Option<&[u8]>) -> Option<usize> {
+        let read_index = read_index?;
+        barcodes
+            .iter()
+            .position(|barcode| hamming_distance(barcode, read_index) <= self.max_mismatches)
+    }
+
+    /// Writes the hopping matrix of read counts with i7 indices in rows and i5 indices
+    /// in columns. Barcodes of the sample sheet are followed by read sequences of unmatched
+    /// indices in order of decreasing read counts, while missing indices and unmatched
+    /// indices over the max number of combinations are counted as `undetermined`.
+    pub fn write_hopping_matrix(&self, writer: &mut impl Write) -> io::Result<()> {
+        let unmatched_counts = self
+            .unmatched_counts
+            .lock()
+            .expect("Counts of unmatched indices are not poisoned");
+        let mut counts: HashMap<(&[u8], &[u8]), usize> = unmatched_counts
+            .iter()
+            .map(|((i7, i5), count)| ((i7.as_slice(), i5.as_slice()), *count))
+            .collect();
+        for (row, i7) in self.i7_barcodes.iter().enumerate() {
+            for (column, i5) in self.i5_barcodes.iter().enumerate() {
+                let count = self.hopping_matrix[row * self.i5_barcodes.len() + column]
+                    .load(Ordering::Relaxed);
+                counts.insert((i7.as_slice(), i5.as_slice()), count);
+            }
+        }
+
+        let rows = get_labels(
+            &self.i7_barcodes,
+            counts.iter().map(|((i7, _), c)| (*i7, *c)),
+        );
+        let columns = get_labels(
+            &self.i5_barcodes,
+            counts.iter().map(|((_, i5), c)| (*i5, *c)),
+        );
+
+        let names: Vec<String> = columns.iter().map(|column| get_name(column)).collect();
+        writeln!(writer, "i7\\i5\t{}", names.join("\t"))?;
+        for row in &rows {
+            let row_counts: Vec<String> = columns
+                .iter()
+                .map(|column| counts.get(&(*row, *column)).unwrap_or(&0).to_string())
+                .collect();
+            writeln!(writer, "{}\t{}", get_name(row), row_counts.join("\t"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the label of the index in the hopping matrix: the matched barcode,
+/// the read sequence of the unmatched index or the empty label of the missing one
+fn get_label(barcodes: &[Vec<u8>], idx: Option<usize>, read_index: Option<&[u8]>) -> Vec<u8> {
+    match (idx, read_index) {
+        (Some(idx), _) => barcodes[idx].clone(),
+        (None, Some(read_index)) => read_index.to_ascii_uppercase(),
+        (None, None) => Vec::new(),
+    }
+}
+
+/// Returns labels of the hopping matrix rows or columns: barcodes of the sample sheet,
+/// read sequences of unmatched indices by decreasing read counts and the undetermined label
+fn get_labels<'a>(
+    barcodes: &'a [Vec<u8>],
+    counts: impl Iterator<Item = (&'a [u8], usize)>,
+) -> Vec<&'a [u8]> {
+    let mut unmatched_counts: HashMap<&[u8], usize> = HashMap::new();
+    for (label, count) in counts {
+        if !label.is_empty() && !barcodes.iter().any(|barcode| barcode == label) {
+            *unmatched_counts.entry(label).or_insert(0) += count;
+        }
+    }
+    let mut unmatched: Vec<(&[u8], usize)> = unmatched_counts.into_iter().collect();
+    unmatched.sort_by(|(label, count), (other_label, other_count)| {
+        other_count.cmp(count).then(label.cmp(other_label))
+    });
+
+    barcodes
+        .iter()
+        .map(Vec::as_slice)
+        .chain(unmatched.into_iter().map(|(label, _)| label))
+        .chain([b"".as_slice()])
+        .collect()
+}
+
+/// Returns the name of the label in the hopping matrix
+fn get_name(label: &[u8]) -> String {
+    match label {
+        [] => UNDETERMINED.to_owned(),
+        _ => String::from_utf8_lossy(label).into_owned(),
+    }
+}
+
+/// Returns index of the barcode in the list, adding it if it is absent
+fn get_or_insert(barcodes: &mut Vec<Vec<u8>>, barcode: &[u8]) -> usize {
+    match barcodes.iter().position(|other| other == barcode) {
+        Some(idx) => idx,
+        None => {
+            barcodes.push(barcode.to_vec());
+            barcodes.len() - 1
+        }
+    }
+}
+
+/// Checks that barcodes differ by more than twice the max mismatches
+fn check_distances(barcodes: &[Vec<u8>], max_mismatches: usize) -> Result<(), Error> {
+    for (idx, barcode) in barcodes.iter().enumerate() {
+        for other in &barcodes[idx + 1..] {
+            let distance = hamming_distance(barcode, other).max(hamming_distance(other, barcode));
+            if distance <= 2 * max_mismatches {
+                return Err(Error::IndexBarcodesDistance(
+                    String::from_utf8_lossy(barcode).into_owned(),
+                    String::from_utf8_lossy(other).into_owned(),
+                    distance,
+                    max_mismatches,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use barkit_extract::error::Error;
+    use rstest::rstest;
+
+    use crate::dual_index::DualIndexAssigner;
+    use crate::sample_sheet::{ReadBarcodes, SampleSheet};
+
+    const SAMPLE_SHEET: &str = "sample,i7,i5\nA,AAAAAA,GGGGGG\nB,CCCCCC,TTTTTT\nC,AAAAAA,TTTTTT";
+
+    #[rstest]
+    #[case(b"AAAAAA", b"GGGGGG", Some(0))]
+    #[case(b"CCCCCA", b"TTTTTA", Some(1))]
+    #[case(b"AAAAAA", b"TTTTTT", Some(2))]
+    #[case(b"CCCCCC", b"GGGGGG", None)]
+    #[case(b"CCCCCC", b"ACGTAC", None)]
+    fn test_assign(#[case] i7: &[u8], #[case] i5: &[u8], #[case] expected: Option<usize>) {
+        let sample_sheet = SampleSheet::parse(SAMPLE_SHEET).unwrap();
+        let assigner = DualIndexAssigner::new(&sample_sheet, 1).unwrap();
+        let read_barcodes = ReadBarcodes {
+            i7: Some(i7),
+            i5: Some(i5),
+            ..Default::default()
+        };
+        assert_eq!(expected, assigner.assign(&read_barcodes));
+    }
+
+    #[test]
+    fn test_write_hopping_matrix() {
+        let sample_sheet = SampleSheet::parse(SAMPLE_SHEET).unwrap();
+        let assigner = DualIndexAssigner::new(&sample_sheet, 1).unwrap();
+        for (i7, i5) in [
+            (b"AAAAAA".as_slice(), Some(b"GGGGGG".as_slice())),
+            (b"CCCCCC", Some(b"GGGGGG")),
+            (b"CCCCCC", Some(b"GGGGGG")),
+            (b"ACGTAC", Some(b"TTTTTT")),
+            (b"GTGTGT", Some(b"CACACA")),
+            (b"GTGTGT", Some(b"CACACA")),
+            (b"AAAAAA", None),
+        ] {
+            assigner.assign(&ReadBarcodes {
+                i7: Some(i7),
+                i5,
+                ..Default::default()
+            });
+        }
+
+        let mut matrix = Vec::new();
+        assigner.write_hopping_matrix(&mut matrix).unwrap();
+        assert_eq!(
+            "i7\\i5\tGGGGGG\tTTTTTT\tCACACA\tundetermined\n\
+             AAAAAA\t1\t0\t0\t1\n\
+             CCCCCC\t2\t0\t0\t0\n\
+             GTGTGT\t0\t0\t2\t0\n\
+             ACGTAC\t0\t1\t0\t0\n\
+             undetermined\t0\t0\t0\t0\n",
+            String::from_utf8(matrix).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("sample,i7,i5\nA,AAAAAA,GGGGGG\nB,AAAACC,TTTTTT", 1)]
+    #[case("sample,i7,i5\nA,AAAAAA,GGGGGG\nB,CCCCCC,GGGTTT", 2)]
+    fn test_close_index_barcodes(#[case] content: &str, #[case] max_mismatches: usize) {
+        let sample_sheet = SampleSheet::parse(content).unwrap();
+        assert!(matches!(
+            DualIndexAssigner::new(&sample_sheet, max_mismatches),
+            Err(Error::IndexBarcodesDistance(_, _, _, _))
+        ));
+    }
+
+    #[rstest]
+    #[case("sample,i7\nA,AAAAAA")]
+    #[case("sample,i7,i5,inline\nA,AAAAAA,GGGGGG,ACGT")]
+    #[case("sample,i7,i5\nA,AAAAAA,GGGGGG\nB,AAAAAA,GGGGGG")]
+    fn test_invalid_dual_index_sample_sheet(#[case] content: &str) {
+        let sample_sheet = SampleSheet::parse(content).unwrap();
+        assert!(matches!(
+            DualIndexAssigner::new(&sample_sheet, 1),
+            Err(Error::InvalidSampleSheet(_))
+        ));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod dual_index;
+pub mod run;
+pub mod sample_sheet;
@@ -0,0 +1,522 @@
+#![allow(clippy::result_large_err)]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use barkit_extract::error::Error;
+use barkit_extract::fastq::{self, CompressionType, FastqReader, FastqWriter, FastqsWriter};
+use barkit_extract::logger;
+use barkit_extract::parse::{BarcodeParser, PatternSelection};
+use barkit_extract::pattern::{BarcodeRegex, BarcodeType, MaxError};
+use rayon::prelude::*;
+use seq_io::fastq::{OwnedRecord, Record, RefRecord};
+
+use crate::dual_index::DualIndexAssigner;
+use crate::sample_sheet::{ReadBarcodes, SampleAssigner, SampleSheet, UNDETERMINED};
+
+/// Name of the file with read counts of samples in the output directory
+const COUNTS_FILE: &str = "counts.tsv";
+
+/// Name of the file with read counts of i7 and i5 barcode combinations in the output directory
+const HOPPING_MATRIX_FILE: &str = "hopping_matrix.tsv";
+
+/// Read with its optional mate assigned to a sample (`None` for undetermined reads)
+type AssignedRead = (Option<usize>, OwnedRecord, Option<OwnedRecord>);
+
+/// Writer of reads of a sample
+enum SampleWriter {
+    /// Writer of single-end reads
+    Single(FastqWriter),
+
+    /// Writer of paired-end reads
+    Paired(FastqsWriter),
+}
+
+/// Assigner of reads to samples
+enum Assigner {
+    /// Assigner by all barcodes of samples
+    Barcodes(SampleAssigner),
+
+    /// Assigner by i7 and i5 barcodes matched independently
+    DualIndex(DualIndexAssigner),
+}
+
+impl Assigner {
+    /// Returns index of the sample of the read barcodes
+    fn assign(&self, read_barcodes: &ReadBarcodes) -> Option<usize> {
+        match self {
+            Assigner::Barcodes(assigner) => assigner.assign(read_barcodes),
+            Assigner::DualIndex(assigner) => assigner.assign(read_barcodes),
+        }
+    }
+}
+
+impl SampleWriter {
+    /// Creates writer of the sample reads in the output directory
+    fn new(
+        out_dir: &Path,
+        name: &str,
+        paired: bool,
+        compression: &CompressionType,
+        threads: usize,
+        force: bool,
+    ) -> Result<Self, Error> {
+        let path = |suffix: &str| {
+            out_dir
+                .join(format!(
+                    "{}{}.fastq{}",
+                    name,
+                    suffix,
+                    compression.extension()
+                ))
+                .to_string_lossy()
+                .into_owned()
+        };
+        Ok(if paired {
+            SampleWriter::Paired(FastqsWriter::new(
+                &path("_R1"),
+                &path("_R2"),
+                compression,
+                threads,
+                force,
+            )?)
+        } else {
+            SampleWriter::Single(FastqWriter::new(&path(""), compression, threads, force)?)
+        })
+    }
+
+    /// Writes reads of the sample
+    fn write_all(&mut self, reads: Vec<(OwnedRecord, Option<OwnedRecord>)>) -> io::Result<()> {
+        match self {
+            SampleWriter::Single(writer) => {
+                writer.write_all(reads.into_iter().map(|(read, _)| read).collect())
+            }
+            SampleWriter::Paired(writer) => writer.write_all(
+                reads
+                    .into_iter()
+                    .filter_map(|(read1, read2)| Some((read1, read2?)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    fq1: String,
+    fq2: Option<String>,
+    index1: Option<String>,
+    index2: Option<String>,
+    sample_sheet: String,
+    out_dir: String,
+    inline_pattern: Option<String>,
+    max_mismatches: usize,
+    dual_index: bool,
+    max_memory: Option<usize>,
+    threads: usize,
+    output_compression: CompressionType,
+    quiet: bool,
+    force: bool,
+) {
+    let mut logger = logger::Logger::new(3, quiet);
+    logger.message("Estimating reads count...");
+
+    let reads_number = FastqReader::count_reads(&fq1, threads, max_memory);
+    logger.set_progress_bar(reads_number);
+
+    logger.message("Reading sample sheet...");
+
+    let sample_sheet = SampleSheet::from_file(&sample_sheet).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let assigner = if dual_index {
+        Assigner::DualIndex(
+            DualIndexAssigner::new(&sample_sheet, max_mismatches).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+        )
+    } else {
+        Assigner::Barcodes(SampleAssigner::new(&sample_sheet, max_mismatches))
+    };
+    let inline_parser = create_inline_parser(&sample_sheet, inline_pattern, max_mismatches)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+
+    let mut reader = open_reader(&fq1, threads, max_memory);
+    let mut mate_reader = fq2
+        .as_ref()
+        .map(|fq2| open_reader(fq2, threads, max_memory));
+    let mut index_readers: Vec<Option<FastqReader>> = [&index1, &index2]
+        .into_iter()
+        .map(|index| {
+            index
+                .as_ref()
+                .map(|path| open_reader(path, threads, max_memory))
+        })
+        .collect();
+
+    let out_dir = PathBuf::from(out_dir);
+    let mut writers = create_writers(
+        &out_dir,
+        &sample_sheet,
+        fq2.is_some(),
+        &output_compression,
+        threads,
+        force,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut counts_file =
+        create_counts_file(&out_dir.join(COUNTS_FILE), force).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let mut hopping_matrix_file = dual_index.then(|| {
+        create_counts_file(&out_dir.join(HOPPING_MATRIX_FILE), force).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    let mut counts = vec![0; writers.len()];
+
+    logger.message("Demultiplexing reads...");
+
+    while let Some(records) = reader.read_record_set() {
+        let records = records.into_iter().collect::<Vec<_>>();
+        let mates = mate_reader.as_mut().map(|mate_reader| {
+            read_records(
+                mate_reader,
+                fq2.as_deref(),
+                &records,
+                Error::MateReadsNumber,
+            )
+        });
+        let index_records: Vec<Option<Vec<OwnedRecord>>> = index_readers
+            .iter_mut()
+            .zip([&index1, &index2])
+            .map(|(index_reader, path)| {
+                index_reader.as_mut().map(|index_reader| {
+                    read_records(
+                        index_reader,
+                        path.as_deref(),
+                        &records,
+                        Error::IndexReadsNumber,
+                    )
+                })
+            })
+            .collect();
+
+        let assigned_reads =
+            assign_reads(&records, mates, &index_records, &assigner, &inline_parser);
+
+        let mut sample_reads: Vec<Vec<(OwnedRecord, Option<OwnedRecord>)>> =
+            vec![Vec::new(); writers.len()];
+        for (sample, read1, read2) in assigned_reads {
+            // Undetermined reads are written to the last output
+            let idx = sample.unwrap_or(writers.len() - 1);
+            counts[idx] += 1;
+            sample_reads[idx].push((read1, read2));
+        }
+        for (writer, reads) in writers.iter_mut().zip(sample_reads) {
+            writer.write_all(reads).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+        }
+
+        logger.increment_progress(records.len());
+    }
+
+    if let Some(mate_reader) = &mut mate_reader {
+        check_reader_exhausted(mate_reader, fq2.as_deref(), Error::MateReadsNumber);
+    }
+    for (reader, path) in index_readers.iter_mut().zip([&index1, &index2]) {
+        if let Some(reader) = reader {
+            check_reader_exhausted(reader, path.as_deref(), Error::IndexReadsNumber);
+        }
+    }
+
+    let names: Vec<&str> = sample_sheet
+        .samples
+        .iter()
+        .map(|sample| sample.name.as_str())
+        .chain([UNDETERMINED])
+        .collect();
+    write_counts(&mut counts_file, &names, &counts).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let (Assigner::DualIndex(assigner), Some(file)) = (&assigner, &mut hopping_matrix_file) {
+        assigner.write_hopping_matrix(file).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    logger.summary(&summary(&names, &counts));
+    logger.final_message();
+}
+
+/// Opens the FASTQ reader or exits with the error
+fn open_reader(path: &str, threads: usize, max_memory: Option<usize>) -> FastqReader {
+    FastqReader::new(path, threads, max_memory).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Reads the same number of records as in the record set of the forward FASTQ
+/// and checks that they have the same read names. The error of reads number is
+/// constructed for the FASTQ type, i.e. the reverse mate or the index.
+fn read_records(
+    reader: &mut FastqReader,
+    path: Option<&str>,
+    records: &[RefRecord],
+    reads_number_error: fn(String) -> Error,
+) -> Vec<OwnedRecord> {
+    let path = path.unwrap_or_default();
+    let mate_records = reader.read_records(records.len()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if mate_records.len() < records.len() {
+        eprintln!("{}", reads_number_error(path.to_owned()));
+        std::process::exit(1);
+    }
+    fastq::check_read_names(
+        path,
+        records.iter().map(|record| record.head()),
+        &mate_records,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    mate_records
+}
+
+/// Checks that the FASTQ does not contain more reads than the forward FASTQ
+fn check_reader_exhausted(
+    reader: &mut FastqReader,
+    path: Option<&str>,
+    reads_number_error: fn(String) -> Error,
+) {
+    let is_exhausted = reader.is_exhausted().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if !is_exhausted {
+        eprintln!(
+            "{}",
+            reads_number_error(path.unwrap_or_default().to_owned())
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Creates the parser of inline barcodes (`SB`) at the start of forward reads,
+/// if the sample sheet contains inline barcodes. The default pattern captures
+/// the inline barcode length, which must be the same for all samples. The pattern
+/// only locates inline barcodes, which are matched to samples with max mismatches,
+/// so adapters of the custom pattern are matched with the same max error.
+fn create_inline_parser(
+    sample_sheet: &SampleSheet,
+    inline_pattern: Option<String>,
+    max_mismatches: usize,
+) -> Result<Option<BarcodeParser>, Error> {
+    let Some((min_len, max_len)) = sample_sheet.get_inline_len() else {
+        return Ok(None);
+    };
+    let pattern = match inline_pattern {
+        Some(pattern) => pattern,
+        None if min_len == max_len => {
+            format!("^(?<{}>[ATGCN]{{{}}})", BarcodeType::Sample, max_len)
+        }
+        None => return Err(Error::InlineBarcodesLength),
+    };
+
+    let barcode_regex =
+        BarcodeRegex::new(&pattern, MaxError::Count(max_mismatches), false, None, None)?;
+    if !barcode_regex
+        .get_barcode_types()
+        .contains(&BarcodeType::Sample)
+    {
+        return Err(Error::BarcodeCaptureGroupNotFound(
+            BarcodeType::Sample.to_string(),
+        ));
+    }
+
+    Ok(BarcodeParser::new(
+        &[barcode_regex],
+        PatternSelection::First,
+        false,
+        false,
+        None,
+        Vec::new(),
+        false,
+        false,
+    ))
+}
+
+/// Creates writers of samples and undetermined reads (the last one) in the output directory
+fn create_writers(
+    out_dir: &Path,
+    sample_sheet: &SampleSheet,
+    paired: bool,
+    compression: &CompressionType,
+    threads: usize,
+    force: bool,
+) -> Result<Vec<SampleWriter>, Error> {
+    fs::create_dir_all(out_dir)?;
+    sample_sheet
+        .samples
+        .iter()
+        .map(|sample| sample.name.as_str())
+        .chain([UNDETERMINED])
+        .map(|name| SampleWriter::new(out_dir, name, paired, compression, threads, force))
+        .collect()
+}
+
+/// Returns sequences of index reads from the read header comment, e.g. `1:N:0:ACGT+TTGC`
+fn get_header_indices(head: &[u8]) -> (Option<&[u8]>, Option<&[u8]>) {
+    let Some(comment) = head
+        .iter()
+        .position(|&byte| byte == b' ')
+        .map(|idx| &head[idx + 1..])
+    else {
+        return (None, None);
+    };
+    let Some(indices) = comment.rsplit(|&byte| byte == b':').next() else {
+        return (None, None);
+    };
+    let mut indices = indices
+        .split(|&byte| byte == b'+')
+        .filter(|index| !index.is_empty());
+    (indices.next(), indices.next())
+}
+
+/// Assigns reads to samples in parallel. Inline barcodes are trimmed from assigned reads.
+fn assign_reads(
+    records: &[RefRecord],
+    mates: Option<Vec<OwnedRecord>>,
+    index_records: &[Option<Vec<OwnedRecord>>],
+    assigner: &Assigner,
+    inline_parser: &Option<BarcodeParser>,
+) -> Vec<AssignedRead> {
+    let mates: Vec<Option<OwnedRecord>> = match mates {
+        Some(mates) => mates.into_iter().map(Some).collect(),
+        None => vec![None; records.len()],
+    };
+
+    records
+        .par_iter()
+        .zip(mates)
+        .enumerate()
+        .map(|(idx, (record, mate))| {
+            let header_indices = get_header_indices(record.head());
+            let index =
+                |position: usize, header_index: Option<&'_ [u8]>| match &index_records[position] {
+                    Some(index_records) => Some(index_records[idx].seq.clone()),
+                    None => header_index.map(<[u8]>::to_vec),
+                };
+            let i7 = index(0, header_indices.0);
+            let i5 = index(1, header_indices.1);
+
+            let parsed_read = inline_parser
+                .as_ref()
+                .map(|parser| parser.parse_barcodes(record));
+            let inline = match &parsed_read {
+                Some(Some(parsed_read)) => parsed_read
+                    .get_barcode(&BarcodeType::Sample.to_string())
+                    .map(|barcode| barcode.seq.clone()),
+                _ => None,
+            };
+
+            let sample = assigner.assign(&ReadBarcodes {
+                i7: i7.as_deref(),
+                i5: i5.as_deref(),
+                inline: inline.as_deref(),
+            });
+            let read = match (sample, parsed_read) {
+                (Some(_), Some(Some(parsed_read))) => parsed_read.record,
+                _ => record.to_owned_record(),
+            };
+            (sample, read, mate)
+        })
+        .collect()
+}
+
+/// Creates the file of the read counts table before demultiplexing to check it can be written
+fn create_counts_file(path: &Path, force: bool) -> io::Result<File> {
+    if force {
+        File::create(path)
+    } else {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} is already existed, use --force to override",
+                        path.display()
+                    ),
+                ),
+                _ => e,
+            })
+    }
+}
+
+/// Writes the table of read counts of samples
+fn write_counts(file: &mut File, names: &[&str], counts: &[usize]) -> io::Result<()> {
+    writeln!(file, "sample\treads")?;
+    for (name, count) in names.iter().zip(counts) {
+        writeln!(file, "{}\t{}", name, count)?;
+    }
+    Ok(())
+}
+
+/// Returns summary of read counts of samples
+fn summary(names: &[&str], counts: &[usize]) -> String {
+    let total = counts.iter().sum::<usize>().max(1) as f64;
+    names
+        .iter()
+        .zip(counts)
+        .map(|(name, count)| {
+            format!(
+                "{}: {} reads ({:.2}%)",
+                name,
+                count,
+                *count as f64 / total * 100.0
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::run::get_header_indices;
+
+    #[rstest]
+    #[case(b"r1 1:N:0:ACGT+TTGC", Some(b"ACGT".as_slice()), Some(b"TTGC".as_slice()))]
+    #[case(b"r1 1:N:0:ACGT", Some(b"ACGT".as_slice()), None)]
+    #[case(b"r1", None, None)]
+    fn test_get_header_indices(
+        #[case] head: &[u8],
+        #[case] i7: Option<&[u8]>,
+        #[case] i5: Option<&[u8]>,
+    ) {
+        assert_eq!((i7, i5), get_header_indices(head));
+    }
+}
@@ -0,0 +1,384 @@
+#![allow(clippy::result_large_err)]
+
+use std::cmp::Reverse;
+use std::fs;
+
+use barkit_extract::error::Error;
+
+/// Name of the output of reads not assigned to any sample
+pub const UNDETERMINED: &str = "undetermined";
+
+/// Nucleotides allowed in sample barcodes
+const NUCLEOTIDES: &[u8] = b"ACGTN";
+
+/// Sample with its barcodes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// Name of the sample used in output file names
+    pub name: String,
+
+    /// Barcode of the first index read (i7)
+    pub i7: Option<Vec<u8>>,
+
+    /// Barcode of the second index read (i5)
+    pub i5: Option<Vec<u8>>,
+
+    /// Inline barcode at the start of forward reads
+    pub inline: Option<Vec<u8>>,
+}
+
+/// Barcodes of a read to assign it to a sample
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadBarcodes<'a> {
+    /// Sequence of the first index read (i7)
+    pub i7: Option<&'a [u8]>,
+
+    /// Sequence of the second index read (i5)
+    pub i5: Option<&'a [u8]>,
+
+    /// Inline barcode captured from the forward read
+    pub inline: Option<&'a [u8]>,
+}
+
+/// Samples to demultiplex reads into
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SampleSheet {
+    /// Samples in order of the sample sheet
+    pub samples: Vec<Sample>,
+}
+
+impl SampleSheet {
+    /// Reads the sample sheet from the file
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the comma or tab separated sample sheet with a header of `sample` column
+    /// and any of `i7`, `i5` and `inline` barcode columns. Empty barcode cells are allowed
+    /// (e.g. i5 of single-index samples), but each sample must have a barcode.
+    /// Empty lines and lines starting with `#` are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_demux::sample_sheet::SampleSheet;
+    ///
+    /// let sample_sheet = SampleSheet::parse("sample,i7,i5\nA,ACGTACGT,TTGCAAGC\nB,GGTTCCAA,CATGCATG").unwrap();
+    ///
+    /// assert_eq!("B", sample_sheet.samples[1].name);
+    /// assert_eq!(Some(b"ACGTACGT".to_vec()), sample_sheet.samples[0].i7);
+    /// ```
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let header = lines
+            .next()
+            .ok_or(Error::InvalidSampleSheet("header is missing".to_owned()))?;
+        let delimiter = if header.contains('\t') { '\t' } else { ',' };
+        let columns: Vec<&str> = header.split(delimiter).map(str::trim).collect();
+        let column = |name: &str| columns.iter().position(|column| *column == name);
+
+        let sample_column = column("sample").ok_or(Error::InvalidSampleSheet(
+            "sample column is missing".to_owned(),
+        ))?;
+        let (i7_column, i5_column, inline_column) = (column("i7"), column("i5"), column("inline"));
+        if i7_column.is_none() && i5_column.is_none() && inline_column.is_none() {
+            return Err(Error::InvalidSampleSheet(
+                "at least one of i7, i5 or inline barcode columns is expected".to_owned(),
+            ));
+        }
+
+        let mut samples: Vec<Sample> = Vec::new();
+        for line in lines {
+            let values: Vec<&str> = line.split(delimiter).map(str::trim).collect();
+            if values.len() != columns.len() {
+                return Err(Error::InvalidSampleSheet(format!(
+                    "line {} contains {} values, expected {}",
+                    line,
+                    values.len(),
+                    columns.len()
+                )));
+            }
+            let barcode = |column: Option<usize>| {
+                column
+                    .filter(|idx| !values[*idx].is_empty())
+                    .map(|idx| parse_barcode(values[idx]))
+                    .transpose()
+            };
+
+            let sample = Sample {
+                name: parse_name(values[sample_column])?,
+                i7: barcode(i7_column)?,
+                i5: barcode(i5_column)?,
+                inline: barcode(inline_column)?,
+            };
+            if sample.i7.is_none() && sample.i5.is_none() && sample.inline.is_none() {
+                return Err(Error::InvalidSampleSheet(format!(
+                    "sample {} does not have any barcode",
+                    sample.name
+                )));
+            }
+            if samples.iter().any(|other| other.name == sample.name) {
+                return Err(Error::InvalidSampleSheet(format!(
+                    "sample {} is provided more than once",
+                    sample.name
+                )));
+            }
+            samples.push(sample);
+        }
+
+        if samples.is_empty() {
+            return Err(Error::InvalidSampleSheet(
+                "sample sheet does not contain any sample".to_owned(),
+            ));
+        }
+
+        Ok(Self { samples })
+    }
+
+    /// Returns lengths of inline barcodes, if they are provided
+    pub fn get_inline_len(&self) -> Option<(usize, usize)> {
+        let lens = self
+            .samples
+            .iter()
+            .filter_map(|sample| sample.inline.as_ref().map(Vec::len));
+        Some((lens.clone().min()?, lens.max()?))
+    }
+}
+
+/// Checks that the sample name can be used in output file names
+fn parse_name(name: &str) -> Result<String, Error> {
+    if name.is_empty()
+        || name == UNDETERMINED
+        || name.contains(['/', '\\'])
+        || name.starts_with('.')
+    {
+        return Err(Error::InvalidSampleSheet(format!(
+            "invalid sample name {:?}",
+            name
+        )));
+    }
+    Ok(name.to_owned())
+}
+
+/// Converts the barcode to upper case and checks its nucleotides
+fn parse_barcode(barcode: &str) -> Result<Vec<u8>, Error> {
+    let barcode = barcode.to_ascii_uppercase().into_bytes();
+    if !barcode.iter().all(|base| NUCLEOTIDES.contains(base)) {
+        return Err(Error::InvalidSampleSheet(format!(
+            "invalid barcode {:?}",
+            String::from_utf8_lossy(&barcode)
+        )));
+    }
+    Ok(barcode)
+}
+
+/// Assigns reads to samples by their barcodes allowing mismatches
+pub struct SampleAssigner {
+    /// Samples to assign reads to
+    samples: Vec<Sample>,
+
+    /// Max number of mismatches in each barcode of the sample
+    max_mismatches: usize,
+}
+
+impl SampleAssigner {
+    /// Creates `SampleAssigner` instance
+    pub fn new(sample_sheet: &SampleSheet, max_mismatches: usize) -> Self {
+        Self {
+            samples: sample_sheet.samples.clone(),
+            max_mismatches,
+        }
+    }
+
+    /// Returns index of the sample with the most matched barcodes and then with the least
+    /// total number of mismatches in them, so that dual-index samples take precedence over
+    /// single-index samples with the same i7. Returns `None`, if no sample has all barcodes
+    /// within the max mismatches or several samples are matched equally.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_demux::sample_sheet::{ReadBarcodes, SampleAssigner, SampleSheet};
+    ///
+    /// let sample_sheet = SampleSheet::parse("sample,i7\nA,AAAA\nB,CCCC").unwrap();
+    /// let assigner = SampleAssigner::new(&sample_sheet, 1);
+    ///
+    /// let read_barcodes = ReadBarcodes { i7: Some(b"CCCA"), ..Default::default() };
+    /// assert_eq!(Some(1), assigner.assign(&read_barcodes));
+    /// ```
+    pub fn assign(&self, read_barcodes: &ReadBarcodes) -> Option<usize> {
+        let mut best: Option<(usize, (Reverse<usize>, usize))> = None;
+        let mut is_tie = false;
+
+        for (idx, sample) in self.samples.iter().enumerate() {
+            let Some((barcodes, mismatches)) = self.count_mismatches(sample, read_barcodes) else {
+                continue;
+            };
+            let score = (Reverse(barcodes), mismatches);
+            match best {
+                Some((_, best_score)) if score == best_score => is_tie = true,
+                Some((_, best_score)) if score > best_score => {}
+                _ => {
+                    best = Some((idx, score));
+                    is_tie = false;
+                }
+            }
+        }
+
+        best.filter(|_| !is_tie).map(|(idx, _)| idx)
+    }
+
+    /// Returns the number of barcodes of the sample and the total number of mismatches
+    /// in them, if all of them are within the max mismatches
+    fn count_mismatches(
+        &self,
+        sample: &Sample,
+        read_barcodes: &ReadBarcodes,
+    ) -> Option<(usize, usize)> {
+        [
+            (&sample.i7, read_barcodes.i7),
+            (&sample.i5, read_barcodes.i5),
+            (&sample.inline, read_barcodes.inline),
+        ]
+        .into_iter()
+        .filter_map(|(sample_barcode, read_barcode)| {
+            sample_barcode
+                .as_ref()
+                .map(|sample_barcode| (sample_barcode, read_barcode))
+        })
+        .try_fold(
+            (0, 0),
+            |(barcodes, total), (sample_barcode, read_barcode)| {
+                let mismatches = hamming_distance(sample_barcode, read_barcode?);
+                (mismatches <= self.max_mismatches).then_some((barcodes + 1, total + mismatches))
+            },
+        )
+    }
+}
+
+/// Counts mismatches between the barcode and the read sequence prefix of the same length.
+/// Missing read bases are counted as mismatches.
+pub fn hamming_distance(barcode: &[u8], sequence: &[u8]) -> usize {
+    let mismatches = barcode
+        .iter()
+        .zip(sequence)
+        .filter(|(base, read_base)| **base != read_base.to_ascii_uppercase())
+        .count();
+    mismatches + barcode.len().saturating_sub(sequence.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use barkit_extract::error::Error;
+    use rstest::rstest;
+
+    use crate::sample_sheet::{hamming_distance, ReadBarcodes, SampleAssigner, SampleSheet};
+
+    #[rstest]
+    #[case("sample\tinline\nA\tacgt\n# comment\n\nB\tGGCC\n", 2)]
+    #[case("i5,sample,i7\nAAAA,A,CCCC", 1)]
+    #[case("sample,i7,i5\nA,AAAA,\nB,CCCC,GGGG", 2)]
+    fn test_parse_sample_sheet(#[case] content: &str, #[case] samples_number: usize) {
+        let sample_sheet = SampleSheet::parse(content).unwrap();
+        assert_eq!(samples_number, sample_sheet.samples.len());
+        assert_eq!("A", sample_sheet.samples[0].name);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("name,i7\nA,ACGT")]
+    #[case("sample,barcode\nA,ACGT")]
+    #[case("sample,i7\nA,ACGT\nA,GGCC")]
+    #[case("sample,i7\nA,ACGX")]
+    #[case("sample,i7\nundetermined,ACGT")]
+    #[case("sample,i7\nA/B,ACGT")]
+    #[case("sample,i7\nA,ACGT,GGCC")]
+    #[case("sample,i7")]
+    #[case("sample,i7,i5\nA,,")]
+    fn test_parse_invalid_sample_sheet(#[case] content: &str) {
+        assert!(matches!(
+            SampleSheet::parse(content),
+            Err(Error::InvalidSampleSheet(_))
+        ));
+    }
+
+    #[rstest]
+    #[case(Some(b"AAAA".as_slice()), Some(b"GGGG".as_slice()), Some(0))]
+    #[case(Some(b"AATA".as_slice()), Some(b"GGGC".as_slice()), Some(0))]
+    #[case(Some(b"ATTA".as_slice()), Some(b"GGGG".as_slice()), None)]
+    #[case(Some(b"AAAA".as_slice()), None, None)]
+    #[case(Some(b"CCCC".as_slice()), Some(b"TTTT".as_slice()), Some(1))]
+    #[case(Some(b"AAAC".as_slice()), Some(b"GGGG".as_slice()), None)]
+    #[case(Some(b"AAAT".as_slice()), Some(b"GGGG".as_slice()), Some(2))]
+    fn test_assign(
+        #[case] i7: Option<&[u8]>,
+        #[case] i5: Option<&[u8]>,
+        #[case] expected: Option<usize>,
+    ) {
+        let sample_sheet =
+            SampleSheet::parse("sample,i7,i5\nA,AAAA,GGGG\nB,CCCC,TTTT\nC,AAAT,GGGG").unwrap();
+        let assigner = SampleAssigner::new(&sample_sheet, 1);
+        let read_barcodes = ReadBarcodes {
+            i7,
+            i5,
+            ..Default::default()
+        };
+        assert_eq!(expected, assigner.assign(&read_barcodes));
+    }
+
+    #[rstest]
+    #[case(Some(b"AAAA".as_slice()), None, Some(0))]
+    #[case(Some(b"AAAA".as_slice()), Some(b"TTTT".as_slice()), Some(0))]
+    #[case(Some(b"CCCC".as_slice()), Some(b"GGGG".as_slice()), Some(1))]
+    #[case(Some(b"CCCC".as_slice()), None, None)]
+    fn test_assign_mixed_indices(
+        #[case] i7: Option<&[u8]>,
+        #[case] i5: Option<&[u8]>,
+        #[case] expected: Option<usize>,
+    ) {
+        let sample_sheet = SampleSheet::parse("sample,i7,i5\nA,AAAA,\nB,CCCC,GGGG").unwrap();
+        let assigner = SampleAssigner::new(&sample_sheet, 1);
+        let read_barcodes = ReadBarcodes {
+            i7,
+            i5,
+            ..Default::default()
+        };
+        assert_eq!(expected, assigner.assign(&read_barcodes));
+    }
+
+    #[rstest]
+    #[case(Some(b"AAAA".as_slice()), Some(b"GGGG".as_slice()), Some(1))]
+    #[case(Some(b"AAAA".as_slice()), Some(b"GGGC".as_slice()), Some(1))]
+    #[case(Some(b"AAAA".as_slice()), Some(b"TTTT".as_slice()), Some(0))]
+    #[case(Some(b"AAAA".as_slice()), None, Some(0))]
+    fn test_assign_single_and_dual_index_collision(
+        #[case] i7: Option<&[u8]>,
+        #[case] i5: Option<&[u8]>,
+        #[case] expected: Option<usize>,
+    ) {
+        let sample_sheet = SampleSheet::parse("sample,i7,i5\nA,AAAA,\nB,AAAA,GGGG").unwrap();
+        let assigner = SampleAssigner::new(&sample_sheet, 1);
+        let read_barcodes = ReadBarcodes {
+            i7,
+            i5,
+            ..Default::default()
+        };
+        assert_eq!(expected, assigner.assign(&read_barcodes));
+    }
+
+    #[rstest]
+    #[case(b"ACGT", b"ACGT", 0)]
+    #[case(b"ACGT", b"acgaTT", 1)]
+    #[case(b"ACGT", b"AC", 2)]
+    fn test_hamming_distance(
+        #[case] barcode: &[u8],
+        #[case] sequence: &[u8],
+        #[case] expected: usize,
+    ) {
+        assert_eq!(expected, hamming_distance(barcode, sequence));
+    }
+}
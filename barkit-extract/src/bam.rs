@@ -0,0 +1,291 @@
+#![allow(clippy::result_large_err)]
+
+use crate::error::Error;
+use crate::fastq;
+use crate::parse::{Barcode, ParsedRead, RAW_CELL_BARCODE_TAG};
+
+/// BAM magic bytes
+const BAM_MAGIC_BYTES: &[u8] = b"BAM\x01";
+
+/// Header of the unaligned BAM with reads grouped by names (mates are interleaved)
+const SAM_HEADER: &str = "@HD\tVN:1.6\tSO:unsorted\tGO:query\n";
+
+/// Flag of single-end unmapped reads
+pub const UNPAIRED_FLAG: u16 = 0x4;
+
+/// Flag of forward unmapped reads with unmapped mates
+pub const FIRST_MATE_FLAG: u16 = 0x1 | 0x4 | 0x8 | 0x40;
+
+/// Flag of reverse unmapped reads with unmapped mates
+pub const SECOND_MATE_FLAG: u16 = 0x1 | 0x4 | 0x8 | 0x80;
+
+/// Bin of unmapped reads computed for the region [-1, 0)
+const UNMAPPED_BIN: u16 = 4680;
+
+/// Max length of the read name without the trailing NUL
+const MAX_READ_NAME_LEN: usize = 254;
+
+/// Quality of bases, if the read does not have qualities
+const MISSING_QUALITY: u8 = 0xff;
+
+/// 4-bit codes of nucleotides in BAM records
+const NUCLEOTIDE_CODES: &[u8] = b"=ACMGRSVTWYHKDBN";
+
+/// Returns the BAM header without reference sequences
+pub fn header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(SAM_HEADER.len() + 12);
+    header.extend_from_slice(BAM_MAGIC_BYTES);
+    header.extend_from_slice(&(SAM_HEADER.len() as u32).to_le_bytes());
+    header.extend_from_slice(SAM_HEADER.as_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // number of reference sequences
+    header
+}
+
+/// Returns SAM tags of the barcode sequence and its base qualities (if the tag has
+/// qualities), e.g. `RX`/`QX` for UMIs, `CR`/`CY` for raw cell barcodes and `BC`/`QT`
+/// for sample barcodes. Barcodes with other two-character names are written as is.
+///
+/// # Example
+///
+/// ```
+/// use barkit_extract::bam::get_tags;
+///
+/// assert_eq!(Ok(("RX", Some("QX"))), get_tags("UMI").map_err(|e| e.to_string()));
+/// assert_eq!(Ok(("CB", None)), get_tags("CB").map_err(|e| e.to_string()));
+/// assert_eq!(Ok(("FB", None)), get_tags("FB").map_err(|e| e.to_string()));
+/// assert!(get_tags("CB1").is_err());
+/// ```
+pub fn get_tags(name: &str) -> Result<(&str, Option<&'static str>), Error> {
+    match name {
+        "UMI" | "RX" => Ok(("RX", Some("QX"))),
+        "SB" | "BC" => Ok(("BC", Some("QT"))),
+        "CR" => Ok(("CR", Some("CY"))),
+        _ if name.len() == 2
+            && name.as_bytes()[0].is_ascii_alphabetic()
+            && name.as_bytes()[1].is_ascii_alphanumeric() =>
+        {
+            Ok((name, None))
+        }
+        _ => Err(Error::InvalidSamTag(name.to_owned())),
+    }
+}
+
+/// Returns SAM tags with values of sequences and qualities of barcodes in order.
+/// Cell barcodes not corrected against a whitelist are written only as raw ones (`CR`),
+/// since `CB` is reserved for corrected barcodes.
+pub fn get_barcode_tags(barcodes: &[Barcode]) -> Result<Vec<(&str, &[u8])>, Error> {
+    let has_raw_cell_barcode = barcodes
+        .iter()
+        .any(|barcode| barcode.name == RAW_CELL_BARCODE_TAG);
+
+    let mut tags = Vec::new();
+    for barcode in barcodes {
+        let (tag, qual_tag) = get_tags(&barcode.name)?;
+        if tag == "CB" && !has_raw_cell_barcode {
+            tags.push((RAW_CELL_BARCODE_TAG, barcode.seq.as_slice()));
+            if !barcode.qual.is_empty() {
+                tags.push(("CY", barcode.qual.as_slice()));
+            }
+            continue;
+        }
+        tags.push((tag, barcode.seq.as_slice()));
+        if let Some(qual_tag) = qual_tag.filter(|_| !barcode.qual.is_empty()) {
+            tags.push((qual_tag, barcode.qual.as_slice()));
+        }
+    }
+    Ok(tags)
+}
+
+/// Encodes the read into the unaligned BAM record with barcodes in SAM tags
+pub fn encode_record(read: &ParsedRead, flag: u16) -> Result<Vec<u8>, Error> {
+    let name = get_read_name(&read.record.head);
+    if name.len() > MAX_READ_NAME_LEN {
+        return Err(Error::ReadNameLength(
+            String::from_utf8_lossy(name).into_owned(),
+        ));
+    }
+    let seq = &read.record.seq;
+
+    let mut record = Vec::with_capacity(36 + name.len() + seq.len() * 2);
+    record.extend_from_slice(&[0; 4]); // block size is set at the end
+    record.extend_from_slice(&(-1i32).to_le_bytes()); // reference sequence
+    record.extend_from_slice(&(-1i32).to_le_bytes()); // position
+    record.push(name.len() as u8 + 1);
+    record.push(0); // mapping quality
+    record.extend_from_slice(&UNMAPPED_BIN.to_le_bytes());
+    record.extend_from_slice(&0u16.to_le_bytes()); // number of CIGAR operations
+    record.extend_from_slice(&flag.to_le_bytes());
+    record.extend_from_slice(&(seq.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(-1i32).to_le_bytes()); // mate reference sequence
+    record.extend_from_slice(&(-1i32).to_le_bytes()); // mate position
+    record.extend_from_slice(&0i32.to_le_bytes()); // template length
+    record.extend_from_slice(name);
+    record.push(0);
+
+    for bases in seq.chunks(2) {
+        let code = |base: u8| {
+            NUCLEOTIDE_CODES
+                .iter()
+                .position(|code| *code == base.to_ascii_uppercase())
+                .unwrap_or(NUCLEOTIDE_CODES.len() - 1) as u8
+        };
+        record.push(code(bases[0]) << 4 | bases.get(1).map_or(0, |base| code(*base)));
+    }
+    if read.record.qual.len() == seq.len() {
+        record.extend(read.record.qual.iter().map(|qual| qual.saturating_sub(33)));
+    } else {
+        record.extend(std::iter::repeat(MISSING_QUALITY).take(seq.len()));
+    }
+
+    for (tag, value) in get_barcode_tags(&read.barcodes)? {
+        record.extend_from_slice(tag.as_bytes());
+        record.push(b'Z');
+        record.extend_from_slice(value);
+        record.push(0);
+    }
+
+    let block_size = (record.len() - 4) as u32;
+    record[..4].copy_from_slice(&block_size.to_le_bytes());
+    Ok(record)
+}
+
+/// Returns the read name without the comment and the mate suffix (`/1` or `/2`),
+/// or `*` if the read has no name
+fn get_read_name(head: &[u8]) -> &[u8] {
+    match fastq::get_read_name(head) {
+        [] => b"*",
+        name => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use seq_io::fastq::OwnedRecord;
+
+    use crate::bam::{
+        encode_record, get_barcode_tags, get_read_name, header, FIRST_MATE_FLAG, UNPAIRED_FLAG,
+    };
+    use crate::parse::{Barcode, BarcodeCombiner, MateOrder, ParsedRead};
+
+    #[test]
+    fn test_header() {
+        let header = header();
+        assert_eq!(b"BAM\x01", &header[..4]);
+        assert_eq!(
+            header.len() - 12,
+            u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize
+        );
+        assert_eq!(&[0, 0, 0, 0], &header[header.len() - 4..]);
+    }
+
+    #[rstest]
+    #[case(b"read1", b"read1")]
+    #[case(b"read1/2 UMI:ACGT", b"read1")]
+    #[case(b"read1 1:N:0:ACGT", b"read1")]
+    #[case(b"", b"*")]
+    fn test_get_read_name(#[case] head: &[u8], #[case] expected: &[u8]) {
+        assert_eq!(expected, get_read_name(head));
+    }
+
+    #[test]
+    fn test_encode_record() {
+        let read = ParsedRead {
+            record: OwnedRecord {
+                head: b"r1/1 comment".to_vec(),
+                seq: b"ACGTN".to_vec(),
+                qual: b"I#III".to_vec(),
+            },
+            barcodes: vec![
+                Barcode::new("UMI", b"AAC", b"II#"),
+                Barcode::new("CB", b"GG", b""),
+            ],
+        };
+        let record = encode_record(&read, FIRST_MATE_FLAG).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&63u32.to_le_bytes());
+        expected.extend_from_slice(&(-1i32).to_le_bytes());
+        expected.extend_from_slice(&(-1i32).to_le_bytes());
+        expected.extend_from_slice(&[3, 0]);
+        expected.extend_from_slice(&4680u16.to_le_bytes());
+        expected.extend_from_slice(&0u16.to_le_bytes());
+        expected.extend_from_slice(&77u16.to_le_bytes());
+        expected.extend_from_slice(&5u32.to_le_bytes());
+        expected.extend_from_slice(&(-1i32).to_le_bytes());
+        expected.extend_from_slice(&(-1i32).to_le_bytes());
+        expected.extend_from_slice(&0i32.to_le_bytes());
+        expected.extend_from_slice(b"r1\0");
+        expected.extend_from_slice(&[0x12, 0x48, 0xf0]);
+        expected.extend_from_slice(&[40, 2, 40, 40, 40]);
+        expected.extend_from_slice(b"RXZAAC\0QXZII#\0CRZGG\0");
+        assert_eq!(expected, record);
+    }
+
+    #[test]
+    fn test_encode_record_without_qualities() {
+        let read = ParsedRead {
+            record: OwnedRecord {
+                head: b"r1".to_vec(),
+                seq: b"AC".to_vec(),
+                qual: Vec::new(),
+            },
+            barcodes: vec![
+                Barcode::new("CR", b"TT", b"II"),
+                Barcode::new("CB", b"TA", b"II"),
+            ],
+        };
+        let record = encode_record(&read, UNPAIRED_FLAG).unwrap();
+        assert!(record.ends_with(b"\x12\xff\xffCRZTT\0CYZII\0CBZTA\0"));
+    }
+
+    #[test]
+    fn test_get_barcode_tags_combined() {
+        let record = OwnedRecord {
+            head: b"r1".to_vec(),
+            seq: b"AC".to_vec(),
+            qual: b"II".to_vec(),
+        };
+        let mut read1 = ParsedRead {
+            record: record.clone(),
+            barcodes: vec![Barcode::new("UMI", b"AAAA", b"IIII")],
+        };
+        let mut read2 = ParsedRead {
+            record,
+            barcodes: vec![Barcode::new("UMI", b"CCCC", b"####")],
+        };
+        BarcodeCombiner::new(
+            vec!["UMI".to_string()],
+            MateOrder::ForwardReverse,
+            "-".to_string(),
+        )
+        .combine(&mut read1, &mut read2);
+
+        let tags = get_barcode_tags(&read1.barcodes).unwrap();
+        assert_eq!(
+            vec![
+                ("RX", b"AAAA-CCCC".as_slice()),
+                ("QX", b"IIII ####".as_slice())
+            ],
+            tags
+        );
+        assert!(encode_record(&read1, FIRST_MATE_FLAG)
+            .unwrap()
+            .ends_with(b"RXZAAAA-CCCC\0QXZIIII ####\0"));
+    }
+
+    #[rstest]
+    #[case("CB1")]
+    #[case("1B")]
+    fn test_encode_record_invalid_tag(#[case] name: &str) {
+        let read = ParsedRead {
+            record: OwnedRecord {
+                head: b"r1".to_vec(),
+                seq: b"AC".to_vec(),
+                qual: b"II".to_vec(),
+            },
+            barcodes: vec![Barcode::new(name, b"AC", b"")],
+        };
+        assert!(encode_record(&read, UNPAIRED_FLAG).is_err());
+    }
+}
@@ -20,6 +20,66 @@ pub enum Error {
     FancyRegex(#[from] fancy_regex::Error),
     #[error("Failed to choose permutation mask")]
     PermutationMaskSize,
+    #[error("Capture group {0} is provided more than once in your pattern")]
+    DuplicateCaptureGroupName(String),
+    #[error("Adapter {0} must contain from 1 to 64 nucleotides")]
+    AdapterLength(String),
+    #[error("Annotation {0} does not follow an adapter sequence")]
+    OrphanAnnotation(String),
+    #[error(
+        "Provided invalid annotation {0}, expected {{e=<max error>}} or {{r=<max error rate>}}"
+    )]
+    InvalidAnnotation(String),
+    #[error("Whitelist {0} does not contain any barcode")]
+    EmptyWhitelist(String),
+    #[error("Preset {0} is not found")]
+    UnknownPreset(String),
+    #[error("Preset {0} does not contain any barcode pattern")]
+    EmptyPreset(String),
+    #[error("Failed to parse presets file: {0}")]
+    InvalidPresetsFile(String),
+    #[error("Index FASTQ {0} contains a different number of reads than the input FASTQ")]
+    IndexReadsNumber(String),
+    #[error("Provided invalid search window {0}, expected <start>..<end>")]
+    InvalidSearchWindow(String),
+    #[error("Pattern {0} contains both CB and round cell barcodes (CB1, CB2, ...)")]
+    CellBarcodeWithRounds(String),
+    #[error(
+        "Pattern contains round cell barcode CB{0}, but only {1} round whitelists are provided"
+    )]
+    RoundWhitelistsNumber(usize, usize),
+    #[error("Barcode of round {0} is not assigned to the round whitelist")]
+    UnassignedRoundBarcode(usize),
+    #[error("Failed to convert pattern {0}: {1}")]
+    InvalidPatternNotation(String, String),
+    #[error("Failed to parse seqspec file: {0}")]
+    InvalidSeqspec(String),
+    #[error("Modality {0} is not found in the seqspec file")]
+    UnknownModality(String),
+    #[error("Failed to parse sample sheet: {0}")]
+    InvalidSampleSheet(String),
+    #[error("Inline barcodes have different lengths, provide the inline barcode pattern")]
+    InlineBarcodesLength,
+    #[error("Index barcodes {0} and {1} differ by {2} mismatches, expected more than twice the max mismatches ({3})")]
+    IndexBarcodesDistance(String, String, usize, usize),
+    #[error("Barcode {0} can not be written as a SAM tag, expected a two-character name")]
+    InvalidSamTag(String),
+    #[error("Read name {0} is longer than 254 characters")]
+    ReadNameLength(String),
+    #[error("Provided invalid header format {0}: {1}")]
+    InvalidHeaderFormat(String, String),
+    #[error("Outputs of unmatched reads must be provided for both mates")]
+    UnmatchedOutputs,
+    #[error("Min quality can not be used with pattern {0}: adapters expanded into regex alternations do not use read qualities")]
+    MinQualityWithExpandedAdapters(String),
+    #[error("Read {1} of FASTQ {0} does not match read {2} of the input FASTQ, reads must be in the same order")]
+    ReadNamesMismatch(String, String, String),
+    #[error("Provided {0} search windows for {1} patterns, expected one window for all patterns or one per pattern")]
+    SearchWindowsNumber(usize, usize),
+    #[error("Reverse FASTQ {0} contains a different number of reads than the forward FASTQ")]
+    MateReadsNumber(String),
+    #[error("Provided sequence {0:?} contains characters other than nucleotides A, C, G, T and N")]
+    InvalidSequence(String),
 }
 
 impl Clone for Error {
@@ -38,6 +98,55 @@ impl Clone for Error {
             Error::PatternNotMatched => Error::PatternNotMatched,
             Error::FancyRegex(err) => Error::FancyRegex(err.clone()),
             Error::PermutationMaskSize => Error::PermutationMaskSize,
+            Error::DuplicateCaptureGroupName(capture_group) => {
+                Error::DuplicateCaptureGroupName(capture_group.clone())
+            }
+            Error::AdapterLength(adapter) => Error::AdapterLength(adapter.clone()),
+            Error::OrphanAnnotation(annotation) => Error::OrphanAnnotation(annotation.clone()),
+            Error::InvalidAnnotation(annotation) => Error::InvalidAnnotation(annotation.clone()),
+            Error::EmptyWhitelist(path) => Error::EmptyWhitelist(path.clone()),
+            Error::UnknownPreset(name) => Error::UnknownPreset(name.clone()),
+            Error::EmptyPreset(name) => Error::EmptyPreset(name.clone()),
+            Error::InvalidPresetsFile(message) => Error::InvalidPresetsFile(message.clone()),
+            Error::IndexReadsNumber(path) => Error::IndexReadsNumber(path.clone()),
+            Error::InvalidSearchWindow(window) => Error::InvalidSearchWindow(window.clone()),
+            Error::CellBarcodeWithRounds(pattern) => Error::CellBarcodeWithRounds(pattern.clone()),
+            Error::RoundWhitelistsNumber(round, whitelists) => {
+                Error::RoundWhitelistsNumber(*round, *whitelists)
+            }
+            Error::UnassignedRoundBarcode(round) => Error::UnassignedRoundBarcode(*round),
+            Error::InvalidPatternNotation(pattern, message) => {
+                Error::InvalidPatternNotation(pattern.clone(), message.clone())
+            }
+            Error::InvalidSeqspec(message) => Error::InvalidSeqspec(message.clone()),
+            Error::UnknownModality(modality) => Error::UnknownModality(modality.clone()),
+            Error::InvalidSampleSheet(message) => Error::InvalidSampleSheet(message.clone()),
+            Error::InlineBarcodesLength => Error::InlineBarcodesLength,
+            Error::IndexBarcodesDistance(barcode, other, distance, max_mismatches) => {
+                Error::IndexBarcodesDistance(
+                    barcode.clone(),
+                    other.clone(),
+                    *distance,
+                    *max_mismatches,
+                )
+            }
+            Error::InvalidSamTag(name) => Error::InvalidSamTag(name.clone()),
+            Error::ReadNameLength(name) => Error::ReadNameLength(name.clone()),
+            Error::InvalidHeaderFormat(format, message) => {
+                Error::InvalidHeaderFormat(format.clone(), message.clone())
+            }
+            Error::UnmatchedOutputs => Error::UnmatchedOutputs,
+            Error::MinQualityWithExpandedAdapters(pattern) => {
+                Error::MinQualityWithExpandedAdapters(pattern.clone())
+            }
+            Error::ReadNamesMismatch(path, name, expected) => {
+                Error::ReadNamesMismatch(path.clone(), name.clone(), expected.clone())
+            }
+            Error::SearchWindowsNumber(windows, patterns) => {
+                Error::SearchWindowsNumber(*windows, *patterns)
+            }
+            Error::MateReadsNumber(path) => Error::MateReadsNumber(path.clone()),
+            Error::InvalidSequence(sequence) => Error::InvalidSequence(sequence.clone()),
         }
     }
 }
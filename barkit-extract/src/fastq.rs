@@ -14,7 +14,10 @@ use gzp::{
 use lz4::{Decoder, EncoderBuilder};
 use seq_io::fastq::{self, OwnedRecord, Reader, RecordSet};
 
+use crate::bam;
 use crate::error::{self, Error};
+use crate::header::HeaderFormat;
+use crate::parse::ParsedRead;
 
 const WRITE_BUFFER_SIZE: usize = 128 * 1024 * 1024; // 128 KB buffer size, you can adjust this size as needed
 
@@ -78,6 +81,15 @@ impl CompressionType {
         }
     }
 
+    /// Returns extension of the output files compressed in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionType::Bgzf | CompressionType::Gzip | CompressionType::Mgzip => ".gz",
+            CompressionType::Lz4 => ".lz4",
+            CompressionType::No => "",
+        }
+    }
+
     /// Detects the compression type (`CompressionType`) of the provided file
     fn detect(path: &Path) -> CompressionType {
         let mut buffer = [0u8; 16];
@@ -163,6 +175,28 @@ impl FastqReader {
             .count()
     }
 
+    /// Reads up to `count` records, used to read files in lockstep with record sets of another file
+    pub fn read_records(&mut self, count: usize) -> Result<Vec<OwnedRecord>, Error> {
+        let mut records = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.reader.next() {
+                Some(record) => records.push(record.map_err(io::Error::other)?.to_owned_record()),
+                None => break,
+            }
+        }
+        Ok(records)
+    }
+
+    /// Returns `true`, if all records of the FASTQ are read
+    pub fn is_exhausted(&mut self) -> Result<bool, Error> {
+        match self.reader.next() {
+            Some(record) => record
+                .map(|_| false)
+                .map_err(|e| io::Error::other(e).into()),
+            None => Ok(true),
+        }
+    }
+
     pub fn read_record_set(&mut self) -> Option<RecordSet> {
         let mut record_set = RecordSet::default();
 
@@ -172,12 +206,54 @@ impl FastqReader {
     }
 }
 
+/// Returns the read name without the comment and the mate suffix (`/1` or `/2`)
+///
+/// # Example
+///
+/// ```
+/// use barkit_extract::fastq::get_read_name;
+///
+/// assert_eq!(b"read1", get_read_name(b"read1/2 1:N:0:ACGT"));
+/// ```
+pub fn get_read_name(head: &[u8]) -> &[u8] {
+    let name = head
+        .split(|byte| byte.is_ascii_whitespace())
+        .next()
+        .unwrap_or_default();
+    match name {
+        [name @ .., b'/', b'1' | b'2'] => name,
+        _ => name,
+    }
+}
+
+/// Checks that records of the FASTQ read in lockstep with the input FASTQ have the same
+/// read names in the same order
+pub fn check_read_names<'a>(
+    path: &str,
+    heads: impl Iterator<Item = &'a [u8]>,
+    records: &[OwnedRecord],
+) -> Result<(), Error> {
+    for (head, record) in heads.zip(records) {
+        if get_read_name(head) != get_read_name(&record.head) {
+            return Err(Error::ReadNamesMismatch(
+                path.to_owned(),
+                String::from_utf8_lossy(get_read_name(&record.head)).into_owned(),
+                String::from_utf8_lossy(get_read_name(head)).into_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub struct FastqsReader {
     /// Forward FASTQ reader
     reader1: FastqReader,
 
     /// Reverse FASTQ reader
     reader2: FastqReader,
+
+    /// Path of the reverse FASTQ
+    fq2: String,
 }
 
 impl FastqsReader {
@@ -190,20 +266,38 @@ impl FastqsReader {
         Ok(Self {
             reader1: FastqReader::new(fq1, threads, max_memory)?,
             reader2: FastqReader::new(fq2, threads, max_memory)?,
+            fq2: fq2.to_owned(),
         })
     }
 
-    pub fn read_record_sets(&mut self) -> Result<(Option<RecordSet>, Option<RecordSet>), Error> {
-        Ok((
-            self.reader1.read_record_set(),
-            self.reader2.read_record_set(),
-        ))
+    /// Reads the record set of forward reads and the same number of reverse reads,
+    /// so mates stay in pairs regardless of read lengths. Returns an error, if the FASTQs
+    /// contain different numbers of reads.
+    pub fn read_record_sets(&mut self) -> Result<Option<(RecordSet, Vec<OwnedRecord>)>, Error> {
+        let Some(record_set) = self.reader1.read_record_set() else {
+            return match self.reader2.is_exhausted()? {
+                true => Ok(None),
+                false => Err(Error::MateReadsNumber(self.fq2.clone())),
+            };
+        };
+        let count = record_set.into_iter().count();
+        let records2 = self.reader2.read_records(count)?;
+        if records2.len() < count {
+            return Err(Error::MateReadsNumber(self.fq2.clone()));
+        }
+        Ok(Some((record_set, records2)))
     }
 }
 
 pub struct FastqWriter {
     /// FASTQ writer
     writer: Rc<Mutex<BufWriter<Box<dyn std::io::Write>>>>,
+
+    /// Write reads as unaligned BAM records instead of FASTQ
+    bam: bool,
+
+    /// Format of barcodes added to FASTQ headers
+    header_format: HeaderFormat,
 }
 
 impl FastqWriter {
@@ -213,22 +307,7 @@ impl FastqWriter {
         threads_num: usize,
         force: bool,
     ) -> Result<Self, error::Error> {
-        let path = Path::new(fq);
-
-        // Check if file exists and handle force logic
-        if path.exists() && !force {
-            return Err(io::Error::new(
-                io::ErrorKind::AlreadyExists,
-                format!("{} is already existed, use --force to override", fq),
-            )
-            .into());
-        }
-
-        let file = if force {
-            File::create(path)?
-        } else {
-            OpenOptions::new().write(true).create_new(true).open(path)?
-        };
+        let file = create_file(fq, force)?;
 
         let writer: Box<dyn Write> = match compression {
             CompressionType::Gzip => Box::new(GzEncoder::new(file, Compression::default())),
@@ -253,30 +332,96 @@ impl FastqWriter {
                 WRITE_BUFFER_SIZE,
                 writer,
             ))),
+            bam: false,
+            header_format: HeaderFormat::default(),
         })
     }
 
-    fn write(&mut self, read: &OwnedRecord) -> Result<(), io::Error> {
+    /// Creates writer of unaligned BAM (BGZF-compressed) with barcodes in SAM tags
+    pub fn new_bam(bam: &str, threads_num: usize, force: bool) -> Result<Self, error::Error> {
+        let file = create_file(bam, force)?;
+
+        let writer: Box<dyn Write> = Box::new(
+            ParCompressBuilder::<Bgzf>::new()
+                .num_threads(threads_num)
+                .expect("Provided unexpected number of threads")
+                .from_writer(file),
+        );
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer);
+        writer.write_all(&bam::header())?;
+
+        Ok(Self {
+            writer: Rc::new(Mutex::new(writer)),
+            bam: true,
+            header_format: HeaderFormat::default(),
+        })
+    }
+
+    /// Writes the read with its barcodes added to the header or, for BAM, into SAM tags
+    fn write(&mut self, read: ParsedRead, flag: u16) -> Result<(), io::Error> {
         let mut writer = self.writer.lock().expect("Failed to lock writer");
-        seq_io::fastq::write_to(&mut *writer, &read.head, &read.seq, &read.qual)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        if self.bam {
+            writer.write_all(&bam::encode_record(&read, flag).map_err(io::Error::other)?)
+        } else {
+            let read = self.header_format.format(read).map_err(io::Error::other)?;
+            seq_io::fastq::write_to(&mut *writer, &read.head, &read.seq, &read.qual)
+                .map_err(io::Error::other)
+        }
+    }
+
+    /// Sets the format of barcodes added to FASTQ headers
+    pub fn set_header_format(&mut self, header_format: HeaderFormat) {
+        self.header_format = header_format;
     }
 
     pub fn write_all(&mut self, result_reads: Vec<OwnedRecord>) -> Result<(), io::Error> {
         for read_record in result_reads {
-            self.write(&read_record)?;
+            self.write(
+                ParsedRead {
+                    record: read_record,
+                    barcodes: Vec::new(),
+                },
+                bam::UNPAIRED_FLAG,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes reads with their barcodes
+    pub fn write_parsed_all(&mut self, parsed_reads: Vec<ParsedRead>) -> Result<(), io::Error> {
+        for parsed_read in parsed_reads {
+            self.write(parsed_read, bam::UNPAIRED_FLAG)?;
         }
 
         Ok(())
     }
 }
 
+/// Creates the output file, which must not exist unless `force` is set
+fn create_file(path: &str, force: bool) -> Result<File, Error> {
+    // Check if file exists and handle force logic
+    if Path::new(path).exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is already existed, use --force to override", path),
+        )
+        .into());
+    }
+
+    Ok(if force {
+        File::create(path)?
+    } else {
+        OpenOptions::new().write(true).create_new(true).open(path)?
+    })
+}
+
 pub struct FastqsWriter {
     /// Forward FASTQ writer
     writer1: FastqWriter,
 
-    /// Reverse FASTQ writer
-    writer2: FastqWriter,
+    /// Reverse FASTQ writer (`None` for BAM with interleaved mates)
+    writer2: Option<FastqWriter>,
 }
 
 impl FastqsWriter {
@@ -289,17 +434,61 @@ impl FastqsWriter {
     ) -> Result<Self, Error> {
         Ok(Self {
             writer1: FastqWriter::new(fq1, compression, threads_num, force)?,
-            writer2: FastqWriter::new(fq2, compression, threads_num, force)?,
+            writer2: Some(FastqWriter::new(fq2, compression, threads_num, force)?),
+        })
+    }
+
+    /// Creates writer of unaligned BAM with interleaved mates
+    pub fn new_bam(bam: &str, threads_num: usize, force: bool) -> Result<Self, Error> {
+        Ok(Self {
+            writer1: FastqWriter::new_bam(bam, threads_num, force)?,
+            writer2: None,
         })
     }
 
+    /// Sets the format of barcodes added to FASTQ headers of both mates
+    pub fn set_header_format(&mut self, header_format: HeaderFormat) {
+        if let Some(writer2) = &mut self.writer2 {
+            writer2.set_header_format(header_format.clone());
+        }
+        self.writer1.set_header_format(header_format);
+    }
+
     pub fn write_all(
         &mut self,
         pe_reads: Vec<(OwnedRecord, OwnedRecord)>,
     ) -> Result<(), io::Error> {
-        for (read1_record, read2_record) in pe_reads {
-            self.writer1.write(&read1_record)?;
-            self.writer2.write(&read2_record)?;
+        self.write_parsed_all(
+            pe_reads
+                .into_iter()
+                .map(|(read1_record, read2_record)| {
+                    (
+                        ParsedRead {
+                            record: read1_record,
+                            barcodes: Vec::new(),
+                        },
+                        ParsedRead {
+                            record: read2_record,
+                            barcodes: Vec::new(),
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Writes mates with their barcodes
+    pub fn write_parsed_all(
+        &mut self,
+        pe_reads: Vec<(ParsedRead, ParsedRead)>,
+    ) -> Result<(), io::Error> {
+        for (read1, read2) in pe_reads {
+            self.writer1.write(read1, bam::FIRST_MATE_FLAG)?;
+            let writer2 = match &mut self.writer2 {
+                Some(writer2) => writer2,
+                None => &mut self.writer1,
+            };
+            writer2.write(read2, bam::SECOND_MATE_FLAG)?;
         }
         Ok(())
     }
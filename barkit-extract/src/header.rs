@@ -0,0 +1,358 @@
+#![allow(clippy::result_large_err)]
+
+use std::str::FromStr;
+
+use seq_io::fastq::OwnedRecord;
+
+use crate::bam;
+use crate::error::Error;
+use crate::parse::{ParsedRead, RAW_CELL_BARCODE_TAG};
+use crate::pattern::BarcodeType;
+
+/// Template placeholder of the read name
+const NAME_PLACEHOLDER: &str = "name";
+
+/// Template placeholder of the original header comment
+const COMMENT_PLACEHOLDER: &str = "comment";
+
+/// Suffix of template placeholders of barcode base qualities, e.g. `{UMI:qual}`
+const QUALITY_SUFFIX: &str = ":qual";
+
+/// Base of the UMI-tools placeholder of cell barcodes not assigned to the whitelist
+const UNASSIGNED_CELL_BASE: u8 = b'N';
+
+/// Index of the UMI field in colon-separated Illumina read names
+const ILLUMINA_UMI_FIELD: usize = 7;
+
+/// Format of barcodes added to read headers
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Barcodes appended to the comment as `UMI:SEQ:QUAL`
+    #[default]
+    Barkit,
+
+    /// Cell barcode and UMI appended to the read name (`NAME_CB_UMI`) as expected by UMI-tools
+    UmiTools,
+
+    /// UMI in the 8th colon-separated field of the read name as in Illumina reads
+    Illumina,
+
+    /// Comment of SAM tags (e.g. `RX:Z:SEQ`) to copy into alignments with `bwa -C`
+    Sam,
+
+    /// User-defined template, e.g. `{name}_{UMI} CB:Z:{CB}`
+    Template(Vec<TemplateSegment>),
+}
+
+/// Part of the user-defined header template
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateSegment {
+    /// Text copied as is
+    Text(String),
+
+    /// Read name (the header before the first whitespace)
+    Name,
+
+    /// Original header comment (the header after the first whitespace)
+    Comment,
+
+    /// Barcode sequence, empty if the read does not have the barcode
+    Sequence(String),
+
+    /// Barcode base qualities, empty if the read does not have the barcode
+    Quality(String),
+}
+
+impl FromStr for HeaderFormat {
+    type Err = Error;
+
+    /// Parses the built-in header format (`barkit`, `umi-tools`, `illumina` or `sam`)
+    /// or the template with `{name}`, `{comment}`, `{<barcode>}` and `{<barcode>:qual}`
+    /// placeholders
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::header::{HeaderFormat, TemplateSegment};
+    ///
+    /// assert_eq!(HeaderFormat::UmiTools, "umi-tools".parse().unwrap());
+    /// assert_eq!(
+    ///     HeaderFormat::Template(vec![
+    ///         TemplateSegment::Name,
+    ///         TemplateSegment::Text("_".to_string()),
+    ///         TemplateSegment::Sequence("UMI".to_string()),
+    ///     ]),
+    ///     "{name}_{UMI}".parse().unwrap()
+    /// );
+    /// ```
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "barkit" => Ok(HeaderFormat::Barkit),
+            "umi-tools" => Ok(HeaderFormat::UmiTools),
+            "illumina" => Ok(HeaderFormat::Illumina),
+            "sam" => Ok(HeaderFormat::Sam),
+            _ => parse_template(format).map(HeaderFormat::Template),
+        }
+    }
+}
+
+impl HeaderFormat {
+    /// Returns the read record with barcodes added to the header in this format.
+    /// Headers of reads without barcodes are not changed.
+    pub fn format(&self, read: ParsedRead) -> Result<OwnedRecord, Error> {
+        if read.barcodes.is_empty() {
+            return Ok(read.record);
+        }
+        let (name, comment) = split_header(&read.record.head);
+        let seq = |name: &str| read.get_barcode(name).map(|barcode| barcode.seq.as_slice());
+
+        let head = match self {
+            HeaderFormat::Barkit => return Ok(read.into_record()),
+            HeaderFormat::UmiTools => {
+                // Cell barcodes not assigned to the whitelist are replaced with N bases,
+                // so that UMI-tools does not take the UMI for the cell barcode
+                let cell_seq = match seq(&BarcodeType::Cell.to_string()) {
+                    Some(cell_seq) => Some(cell_seq.to_vec()),
+                    None => seq(RAW_CELL_BARCODE_TAG)
+                        .map(|raw_seq| vec![UNASSIGNED_CELL_BASE; raw_seq.len()]),
+                };
+
+                let mut head = name.to_vec();
+                for barcode_seq in [cell_seq.as_deref(), seq(&BarcodeType::Umi.to_string())]
+                    .into_iter()
+                    .flatten()
+                {
+                    head.push(b'_');
+                    head.extend_from_slice(barcode_seq);
+                }
+                join_comment(head, comment)
+            }
+            HeaderFormat::Illumina => {
+                // The UMI replaces the 8th field, if the read name already has it
+                let mut fields = name.split(|&byte| byte == b':').collect::<Vec<_>>();
+                if let Some(umi) = seq(&BarcodeType::Umi.to_string()) {
+                    match fields.get_mut(ILLUMINA_UMI_FIELD) {
+                        Some(field) => *field = umi,
+                        None => fields.push(umi),
+                    }
+                }
+                join_comment(fields.join(&b':'), comment)
+            }
+            HeaderFormat::Sam => {
+                let mut head = name.to_vec();
+                for (idx, (tag, value)) in bam::get_barcode_tags(&read.barcodes)?
+                    .into_iter()
+                    .enumerate()
+                {
+                    head.push(if idx == 0 { b' ' } else { b'\t' });
+                    head.extend_from_slice(tag.as_bytes());
+                    head.extend_from_slice(b":Z:");
+                    head.extend_from_slice(value);
+                }
+                head
+            }
+            HeaderFormat::Template(segments) => {
+                let mut head = Vec::with_capacity(read.record.head.len());
+                for segment in segments {
+                    match segment {
+                        TemplateSegment::Text(text) => head.extend_from_slice(text.as_bytes()),
+                        TemplateSegment::Name => head.extend_from_slice(name),
+                        TemplateSegment::Comment => head.extend_from_slice(comment),
+                        TemplateSegment::Sequence(barcode_name) => {
+                            head.extend_from_slice(seq(barcode_name).unwrap_or_default())
+                        }
+                        TemplateSegment::Quality(barcode_name) => head.extend_from_slice(
+                            read.get_barcode(barcode_name)
+                                .map(|barcode| barcode.qual.as_slice())
+                                .unwrap_or_default(),
+                        ),
+                    }
+                }
+                head
+            }
+        };
+
+        Ok(OwnedRecord {
+            head,
+            seq: read.record.seq,
+            qual: read.record.qual,
+        })
+    }
+}
+
+/// Parses the header template into segments
+fn parse_template(template: &str) -> Result<Vec<TemplateSegment>, Error> {
+    let invalid =
+        |message: &str| Error::InvalidHeaderFormat(template.to_owned(), message.to_owned());
+
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(invalid("unexpected }"));
+        }
+        if start > 0 {
+            segments.push(TemplateSegment::Text(rest[..start].to_owned()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| invalid("placeholder is not closed"))?;
+
+        let placeholder = &rest[start + 1..end];
+        segments.push(match placeholder {
+            NAME_PLACEHOLDER => TemplateSegment::Name,
+            COMMENT_PLACEHOLDER => TemplateSegment::Comment,
+            _ => {
+                let (name, quality) = match placeholder.strip_suffix(QUALITY_SUFFIX) {
+                    Some(name) => (name, true),
+                    None => (placeholder, false),
+                };
+                if name.is_empty() || BarcodeType::parse_type(name).is_err() {
+                    return Err(invalid(&format!(
+                        "unexpected placeholder {{{}}}",
+                        placeholder
+                    )));
+                }
+                if quality {
+                    TemplateSegment::Quality(name.to_owned())
+                } else {
+                    TemplateSegment::Sequence(name.to_owned())
+                }
+            }
+        });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(TemplateSegment::Text(rest.to_owned()));
+    }
+
+    if !segments
+        .iter()
+        .any(|segment| !matches!(segment, TemplateSegment::Text(_)))
+    {
+        return Err(invalid("template does not contain any placeholder"));
+    }
+    Ok(segments)
+}
+
+/// Splits the read header into the name and the comment by the first whitespace
+fn split_header(head: &[u8]) -> (&[u8], &[u8]) {
+    match head.iter().position(|byte| byte.is_ascii_whitespace()) {
+        Some(idx) => (&head[..idx], &head[idx + 1..]),
+        None => (head, &[]),
+    }
+}
+
+/// Appends the comment to the read name, if it is not empty
+fn join_comment(mut head: Vec<u8>, comment: &[u8]) -> Vec<u8> {
+    if !comment.is_empty() {
+        head.push(b' ');
+        head.extend_from_slice(comment);
+    }
+    head
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use seq_io::fastq::OwnedRecord;
+
+    use crate::error::Error;
+    use crate::header::HeaderFormat;
+    use crate::parse::{Barcode, ParsedRead};
+
+    fn get_read(head: &[u8], barcodes: Vec<Barcode>) -> ParsedRead {
+        ParsedRead {
+            record: OwnedRecord {
+                head: head.to_vec(),
+                seq: b"ACGT".to_vec(),
+                qual: b"IIII".to_vec(),
+            },
+            barcodes,
+        }
+    }
+
+    #[rstest]
+    #[case("barkit", b"r1 1:N:0:GG".as_slice(), b"r1 1:N:0:GG UMI:AAC:II# CB:TTG:III".as_slice())]
+    #[case("umi-tools", b"r1 1:N:0:GG", b"r1_TTG_AAC 1:N:0:GG")]
+    #[case(
+        "illumina",
+        b"M1:7:FC:1:11:10:20 1:N:0:GG",
+        b"M1:7:FC:1:11:10:20:AAC 1:N:0:GG"
+    )]
+    #[case("sam", b"r1 1:N:0:GG", b"r1 RX:Z:AAC\tQX:Z:II#\tCR:Z:TTG\tCY:Z:III")]
+    #[case("{name}_{UMI} CB:Z:{CB}", b"r1 1:N:0:GG", b"r1_AAC CB:Z:TTG")]
+    #[case(
+        "{name} {comment} UMI:{UMI}:{UMI:qual} SB:{SB}",
+        b"r1 1:N:0:GG",
+        b"r1 1:N:0:GG UMI:AAC:II# SB:"
+    )]
+    fn test_format(#[case] format: &str, #[case] head: &[u8], #[case] expected: &[u8]) {
+        let header_format: HeaderFormat = format.parse().unwrap();
+        let read = get_read(
+            head,
+            vec![
+                Barcode::new("UMI", b"AAC", b"II#"),
+                Barcode::new("CB", b"TTG", b"III"),
+            ],
+        );
+        assert_eq!(
+            expected,
+            header_format.format(read).unwrap().head.as_slice()
+        );
+    }
+
+    #[rstest]
+    #[case("umi-tools", b"r1 1:N:0:GG".as_slice(), b"r1_NNN_AAC 1:N:0:GG".as_slice())]
+    #[case(
+        "illumina",
+        b"M1:7:FC:1:11:10:20:CCT 1:N:0:GG",
+        b"M1:7:FC:1:11:10:20:AAC 1:N:0:GG"
+    )]
+    #[case("sam", b"r1 1:N:0:GG", b"r1 RX:Z:AAC\tQX:Z:II#\tCR:Z:TTG\tCY:Z:III")]
+    fn test_format_unassigned_cell(
+        #[case] format: &str,
+        #[case] head: &[u8],
+        #[case] expected: &[u8],
+    ) {
+        let header_format: HeaderFormat = format.parse().unwrap();
+        let read = get_read(
+            head,
+            vec![
+                Barcode::new("UMI", b"AAC", b"II#"),
+                Barcode::new("CR", b"TTG", b"III"),
+            ],
+        );
+        assert_eq!(
+            expected,
+            header_format.format(read).unwrap().head.as_slice()
+        );
+    }
+
+    #[rstest]
+    #[case("umi-tools")]
+    #[case("{name}_{UMI}")]
+    fn test_format_without_barcodes(#[case] format: &str) {
+        let header_format: HeaderFormat = format.parse().unwrap();
+        let read = get_read(b"r1 comment", Vec::new());
+        assert_eq!(
+            b"r1 comment".as_slice(),
+            header_format.format(read).unwrap().head.as_slice()
+        );
+    }
+
+    #[rstest]
+    #[case("csv")]
+    #[case("{name")]
+    #[case("name}_{UMI}")]
+    #[case("{name}_{U.MI}")]
+    #[case("{name}_{UMI:seq}")]
+    #[case("{name}_{}")]
+    fn test_invalid_format(#[case] format: &str) {
+        assert!(matches!(
+            format.parse::<HeaderFormat>(),
+            Err(Error::InvalidHeaderFormat(_, _))
+        ));
+    }
+}
@@ -1,6 +1,13 @@
+pub mod bam;
 pub mod error;
 pub mod fastq;
+pub mod header;
 pub mod logger;
+pub mod matcher;
+pub mod notation;
 pub mod parse;
 pub mod pattern;
+pub mod preset;
 pub mod run;
+pub mod seqspec;
+pub mod whitelist;
@@ -101,6 +101,16 @@ impl Logger {
         }
     }
 
+    /// Prints summary statistics of the execution
+    pub fn summary(&self, text: &str) {
+        if !self.quiet {
+            if let Some(ref pb) = self.progress_bar {
+                pb.finish();
+            }
+            println!("{}", text);
+        }
+    }
+
     /// Prints a final message when all steps are completed
     pub fn final_message(&self) {
         if self.progress_bar.is_some() {
@@ -0,0 +1,315 @@
+#![allow(clippy::result_large_err)]
+
+use crate::error::Error;
+
+/// Max length of the adapter sequence, which fits into the bit vector of `AdapterMatcher`
+pub const MAX_ADAPTER_LENGTH: usize = u64::BITS as usize;
+
+/// Offset of Phred quality scores in FASTQ files
+pub const PHRED_OFFSET: u8 = 33;
+
+/// Returns nucleotides matched by the IUPAC code (https://www.bioinformatics.org/sms/iupac.html)
+///
+/// # Example
+///
+/// ```
+/// use barkit_extract::matcher::get_iupac_bases;
+///
+/// assert_eq!(Some(b"AG".as_slice()), get_iupac_bases(b'r'));
+/// assert_eq!(None, get_iupac_bases(b'X'));
+/// ```
+pub fn get_iupac_bases(code: u8) -> Option<&'static [u8]> {
+    match code.to_ascii_uppercase() {
+        b'A' => Some(b"A"),
+        b'C' => Some(b"C"),
+        b'G' => Some(b"G"),
+        b'T' => Some(b"T"),
+        b'R' => Some(b"AG"),
+        b'Y' => Some(b"CT"),
+        b'S' => Some(b"CG"),
+        b'W' => Some(b"AT"),
+        b'K' => Some(b"GT"),
+        b'M' => Some(b"AC"),
+        b'B' => Some(b"CGT"),
+        b'D' => Some(b"AGT"),
+        b'H' => Some(b"ACT"),
+        b'V' => Some(b"ACG"),
+        b'N' => Some(b"ACGTN"),
+        _ => None,
+    }
+}
+
+/// Approximate occurrence of the adapter sequence in a read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdapterMatch {
+    /// Start position of the occurrence
+    pub start: usize,
+
+    /// End position (exclusive) of the occurrence
+    pub end: usize,
+
+    /// Number of errors between the adapter and the occurrence
+    pub errors: usize,
+}
+
+/// Bit-parallel approximate matcher of the adapter sequence (Wu-Manber algorithm).
+///
+/// Searching takes `O(n * k)` time for a read of length `n` and `k` allowed errors,
+/// regardless of the adapter length (up to `MAX_ADAPTER_LENGTH` nucleotides).
+#[derive(Clone, Debug)]
+pub struct AdapterMatcher {
+    /// Adapter sequence in upper case
+    adapter: Vec<u8>,
+
+    /// Bit masks of the adapter positions matched by each byte of a read.
+    /// IUPAC codes of the adapter match each of their nucleotides.
+    masks: Vec<u64>,
+
+    /// Max number of errors in the adapter occurrence
+    max_error: usize,
+
+    /// If `true`, insertions and deletions are also counted as errors
+    indels: bool,
+
+    /// Read bases with Phred quality below this threshold match any adapter base
+    min_quality: Option<u8>,
+}
+
+impl AdapterMatcher {
+    /// Creates `AdapterMatcher` instance
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::matcher::AdapterMatcher;
+    ///
+    /// let adapter_matcher = AdapterMatcher::new("atgccat", 1, false, None).unwrap();
+    /// ```
+    pub fn new(
+        adapter: &str,
+        max_error: usize,
+        indels: bool,
+        min_quality: Option<u8>,
+    ) -> Result<Self, Error> {
+        if adapter.is_empty() || adapter.len() > MAX_ADAPTER_LENGTH {
+            return Err(Error::AdapterLength(adapter.to_owned()));
+        }
+
+        let adapter = adapter.to_ascii_uppercase().into_bytes();
+        let mut masks = vec![0u64; 256];
+        for (idx, code) in adapter.iter().enumerate() {
+            let bases = get_iupac_bases(*code).unwrap_or(std::slice::from_ref(code));
+            for base in bases {
+                masks[*base as usize] |= 1 << idx;
+            }
+        }
+
+        Ok(Self {
+            max_error: max_error.min(adapter.len()),
+            adapter,
+            masks,
+            indels,
+            min_quality,
+        })
+    }
+
+    /// Finds all approximate occurrences of the adapter in the read sequence,
+    /// sorted by their start and end positions. Read qualities are used only
+    /// if the min quality is set (missing qualities are considered as high).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::matcher::{AdapterMatch, AdapterMatcher};
+    ///
+    /// let adapter_matcher = AdapterMatcher::new("atgc", 1, false, None).unwrap();
+    ///
+    /// assert_eq!(
+    ///     vec![AdapterMatch { start: 2, end: 6, errors: 1 }],
+    ///     adapter_matcher.find_matches(b"CCATCCCC", b"IIIIIIII")
+    /// );
+    /// ```
+    pub fn find_matches(&self, read_seq: &[u8], read_qual: &[u8]) -> Vec<AdapterMatch> {
+        let mut matches = Vec::new();
+
+        for (end, errors) in self.find_ends(read_seq, read_qual) {
+            if self.indels {
+                matches.extend(self.find_starts(read_seq, read_qual, end));
+            } else {
+                matches.push(AdapterMatch {
+                    start: end - self.adapter.len(),
+                    end,
+                    errors,
+                });
+            }
+        }
+
+        matches.sort_by_key(|adapter_match| (adapter_match.start, adapter_match.end));
+        matches
+    }
+
+    /// Returns difference between lengths of the occurrence and the adapter,
+    /// which is caused by insertions and deletions
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::matcher::{AdapterMatch, AdapterMatcher};
+    ///
+    /// let adapter_matcher = AdapterMatcher::new("atgc", 1, true, None).unwrap();
+    ///
+    /// assert_eq!(1, adapter_matcher.get_length_shift(&AdapterMatch { start: 2, end: 5, errors: 1 }));
+    /// ```
+    pub fn get_length_shift(&self, adapter_match: &AdapterMatch) -> usize {
+        (adapter_match.end - adapter_match.start).abs_diff(self.adapter.len())
+    }
+
+    /// Returns bit mask of the adapter positions matched by the read base.
+    /// Low-quality bases match all positions.
+    fn get_mask(&self, read_seq: &[u8], read_qual: &[u8], idx: usize) -> u64 {
+        let is_low_quality = self.min_quality.is_some_and(|min_quality| {
+            read_qual
+                .get(idx)
+                .is_some_and(|qual| qual.saturating_sub(PHRED_OFFSET) < min_quality)
+        });
+        if is_low_quality {
+            u64::MAX
+        } else {
+            self.masks[read_seq[idx] as usize]
+        }
+    }
+
+    /// Returns end positions of the adapter occurrences with the least number of errors
+    fn find_ends(&self, read_seq: &[u8], read_qual: &[u8]) -> Vec<(usize, usize)> {
+        let last_bit = 1u64 << (self.adapter.len() - 1);
+        let mut states: Vec<u64> = (0..=self.max_error)
+            .map(|errors| match (self.indels, errors) {
+                (false, _) | (true, 0) => 0,
+                (true, errors) => u64::MAX >> (u64::BITS as usize - errors),
+            })
+            .collect();
+        let mut ends = Vec::new();
+
+        for idx in 0..read_seq.len() {
+            let mask = self.get_mask(read_seq, read_qual, idx);
+
+            let mut previous_state = states[0];
+            states[0] = ((states[0] << 1) | 1) & mask;
+
+            for errors in 1..states.len() {
+                let state = states[errors];
+                let mut new_state = (((state << 1) | 1) & mask) | (previous_state << 1) | 1;
+                if self.indels {
+                    new_state |= previous_state | (states[errors - 1] << 1);
+                }
+                states[errors] = new_state;
+                previous_state = state;
+            }
+
+            if let Some(errors) = states.iter().position(|state| state & last_bit != 0) {
+                ends.push((idx + 1, errors));
+            }
+        }
+
+        ends
+    }
+
+    /// Finds start positions of the adapter occurrences ending at the `end` position.
+    /// Aligns the adapter backwards from the `end` with the edit distance.
+    fn find_starts(&self, read_seq: &[u8], read_qual: &[u8], end: usize) -> Vec<AdapterMatch> {
+        let adapter_len = self.adapter.len();
+        let max_len = (adapter_len + self.max_error).min(end);
+
+        // `distances[j]` is the edit distance between the last `j` adapter bases
+        // and the read sequence suffix of the current length
+        let mut distances: Vec<usize> = (0..=adapter_len).collect();
+        let mut starts = Vec::new();
+
+        if distances[adapter_len] <= self.max_error {
+            starts.push(AdapterMatch {
+                start: end,
+                end,
+                errors: distances[adapter_len],
+            });
+        }
+
+        for len in 1..=max_len {
+            let mask = self.get_mask(read_seq, read_qual, end - len);
+            let mut diagonal = distances[0];
+            distances[0] = len;
+
+            for j in 1..=adapter_len {
+                let is_match = mask & (1 << (adapter_len - j)) != 0;
+                let distance = (diagonal + usize::from(!is_match))
+                    .min(distances[j] + 1)
+                    .min(distances[j - 1] + 1);
+                diagonal = distances[j];
+                distances[j] = distance;
+            }
+
+            if distances[adapter_len] <= self.max_error {
+                starts.push(AdapterMatch {
+                    start: end - len,
+                    end,
+                    errors: distances[adapter_len],
+                });
+            }
+        }
+
+        starts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::matcher::{AdapterMatch, AdapterMatcher};
+
+    #[rstest]
+    #[case(vec![(0, 4, 0)], "ATGC", b"ATGC", 0, false)]
+    #[case(vec![], "ATGC", b"ATCC", 0, false)]
+    #[case(vec![(2, 6, 1)], "ATGC", b"CCATCCCC", 1, false)]
+    #[case(vec![], "ATGC", b"CCAGCCCC", 1, false)]
+    #[case(vec![(1, 4, 1), (1, 5, 1)], "ATGC", b"CATCCCC", 1, true)]
+    #[case(vec![(1, 6, 1), (2, 5, 1), (2, 6, 0), (2, 7, 1), (3, 6, 1)], "ATGC", b"CCATGCCC", 1, true)]
+    #[case(vec![(0, 4, 0)], "ryNc", b"ATGC", 0, false)]
+    #[case(vec![(0, 4, 0)], "ryNc", b"GCNC", 0, false)]
+    #[case(vec![], "ryNc", b"CCGC", 0, false)]
+    #[case(vec![(0, 4, 1)], "ryNc", b"CCGC", 1, false)]
+    fn test_find_matches(
+        #[case] expected: Vec<(usize, usize, usize)>,
+        #[case] adapter: &str,
+        #[case] read_seq: &[u8],
+        #[case] max_error: usize,
+        #[case] indels: bool,
+    ) {
+        let adapter_matcher = AdapterMatcher::new(adapter, max_error, indels, None).unwrap();
+        let expected: Vec<AdapterMatch> = expected
+            .into_iter()
+            .map(|(start, end, errors)| AdapterMatch { start, end, errors })
+            .collect();
+        assert_eq!(expected, adapter_matcher.find_matches(read_seq, &[]));
+    }
+
+    #[rstest]
+    #[case(vec![(0, 4, 0)], b"ATCC", b"II#I", Some(20), false)]
+    #[case(vec![], b"ATCC", b"II#I", None, false)]
+    #[case(vec![], b"ATCC", b"II5I", Some(20), false)]
+    #[case(vec![(0, 4, 0)], b"ATCC", b"II4I", Some(20), false)]
+    #[case(vec![(0, 4, 0)], b"ATCC", b"II#I", Some(20), true)]
+    fn test_find_matches_with_qualities(
+        #[case] expected: Vec<(usize, usize, usize)>,
+        #[case] read_seq: &[u8],
+        #[case] read_qual: &[u8],
+        #[case] min_quality: Option<u8>,
+        #[case] indels: bool,
+    ) {
+        let adapter_matcher = AdapterMatcher::new("ATGC", 0, indels, min_quality).unwrap();
+        let expected: Vec<AdapterMatch> = expected
+            .into_iter()
+            .map(|(start, end, errors)| AdapterMatch { start, end, errors })
+            .collect();
+        assert_eq!(expected, adapter_matcher.find_matches(read_seq, read_qual));
+    }
+}
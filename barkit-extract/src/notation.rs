@@ -0,0 +1,271 @@
+#![allow(clippy::result_large_err)]
+
+use crate::error::Error;
+use crate::pattern::BarcodeType;
+
+/// Regex of any nucleotide in the converted patterns
+const NUCLEOTIDE_REGEX: &str = "[ATGCN]";
+
+/// Syntax of the barcode pattern
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PatternSyntax {
+    /// Regex with named capture groups (e.g. ^(?<UMI>[ATGCN]{12}))
+    #[default]
+    Regex,
+
+    /// UMI-tools barcode pattern (e.g. CCCCCCCCNNNNNNNN)
+    UmiTools,
+
+    /// fgbio read structure (e.g. 8B12M+T)
+    ReadStructure,
+}
+
+/// Segment of the read layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Segment {
+    /// Barcode of the specified type and length
+    Barcode(SegmentType, usize),
+
+    /// Skipped bases, which are trimmed with barcodes
+    Skip(usize),
+
+    /// Template bases (the rest of the read)
+    Template,
+}
+
+/// Type of the barcode segment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SegmentType {
+    Cell,
+    Umi,
+    Sample,
+}
+
+impl PatternSyntax {
+    /// Converts the pattern of this syntax into the regex barcode pattern
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::notation::PatternSyntax;
+    ///
+    /// assert_eq!(
+    ///     "^(?<CB>[ATGCN]{8})(?<UMI>[ATGCN]{8})",
+    ///     PatternSyntax::UmiTools.to_regex("CCCCCCCCNNNNNNNN").unwrap()
+    /// );
+    /// assert_eq!(
+    ///     "^(?<SB>[ATGCN]{8})(?<UMI>[ATGCN]{12})",
+    ///     PatternSyntax::ReadStructure.to_regex("8B12M+T").unwrap()
+    /// );
+    /// ```
+    pub fn to_regex(&self, pattern: &str) -> Result<String, Error> {
+        let segments = match self {
+            PatternSyntax::Regex => return Ok(pattern.to_owned()),
+            PatternSyntax::UmiTools => parse_umi_tools_pattern(pattern)?,
+            PatternSyntax::ReadStructure => parse_read_structure(pattern)?,
+        };
+        build_regex(pattern, &segments)
+    }
+}
+
+/// Parses UMI-tools barcode pattern, where `C` is a cell barcode base, `N` is a UMI base
+/// and `X` is a base kept in the read. Only trailing `X` bases are supported, since other
+/// ones would be trimmed from the read, while UMI-tools reattaches them.
+fn parse_umi_tools_pattern(pattern: &str) -> Result<Vec<Segment>, Error> {
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for base in pattern.trim().chars() {
+        let segment = match base {
+            'C' => Segment::Barcode(SegmentType::Cell, 1),
+            'N' => Segment::Barcode(SegmentType::Umi, 1),
+            'X' => Segment::Skip(1),
+            _ => {
+                return Err(Error::InvalidPatternNotation(
+                    pattern.to_owned(),
+                    format!("unexpected character {}, expected C, N or X", base),
+                ))
+            }
+        };
+        match (segments.last_mut(), segment) {
+            (Some(Segment::Barcode(last_type, len)), Segment::Barcode(segment_type, _))
+                if *last_type == segment_type =>
+            {
+                *len += 1
+            }
+            (Some(Segment::Skip(len)), Segment::Skip(_)) => *len += 1,
+            _ => segments.push(segment),
+        }
+    }
+
+    if let Some(Segment::Skip(_)) = segments.last() {
+        segments.pop();
+    }
+    if segments
+        .iter()
+        .any(|segment| matches!(segment, Segment::Skip(_)))
+    {
+        return Err(Error::InvalidPatternNotation(
+            pattern.to_owned(),
+            "only trailing X bases are supported".to_owned(),
+        ));
+    }
+    segments.push(Segment::Template);
+    Ok(segments)
+}
+
+/// Parses fgbio read structure of `<length><type>` segments, where the length is a number
+/// or `+` (the rest of the read) and the type is `T` (template), `B` (sample barcode),
+/// `M` (UMI), `C` (cell barcode) or `S` (skipped bases).
+fn parse_read_structure(pattern: &str) -> Result<Vec<Segment>, Error> {
+    let invalid =
+        |message: &str| Error::InvalidPatternNotation(pattern.to_owned(), message.to_owned());
+    let mut segments = Vec::new();
+    let mut len = String::new();
+
+    for character in pattern.trim().chars() {
+        match character {
+            '0'..='9' | '+' => len.push(character),
+            'T' | 'B' | 'M' | 'C' | 'S' => {
+                let segment_len = match len.as_str() {
+                    "+" => None,
+                    len => Some(
+                        len.parse::<usize>()
+                            .ok()
+                            .filter(|len| *len > 0)
+                            .ok_or(invalid("segment length must be a positive number or +"))?,
+                    ),
+                };
+                len.clear();
+                segments.push(match (character, segment_len) {
+                    ('T', _) => Segment::Template,
+                    (_, None) => return Err(invalid("only template segments can have + length")),
+                    ('B', Some(len)) => Segment::Barcode(SegmentType::Sample, len),
+                    ('M', Some(len)) => Segment::Barcode(SegmentType::Umi, len),
+                    ('C', Some(len)) => Segment::Barcode(SegmentType::Cell, len),
+                    (_, Some(len)) => Segment::Skip(len),
+                });
+            }
+            _ => {
+                return Err(invalid(&format!(
+                    "unexpected character {}, expected T, B, M, C or S segments",
+                    character
+                )))
+            }
+        }
+    }
+    if !len.is_empty() {
+        return Err(invalid("segment type is missing at the end"));
+    }
+
+    Ok(segments)
+}
+
+/// Builds the regex pattern from segments. The pattern is anchored to the read start,
+/// or to the read end, if the template goes first.
+fn build_regex(pattern: &str, segments: &[Segment]) -> Result<String, Error> {
+    let invalid =
+        |message: &str| Error::InvalidPatternNotation(pattern.to_owned(), message.to_owned());
+
+    let templates: Vec<usize> = segments
+        .iter()
+        .enumerate()
+        .filter(|(_, segment)| **segment == Segment::Template)
+        .map(|(idx, _)| idx)
+        .collect();
+    let (barcode_segments, prefix, suffix) = match templates.as_slice() {
+        [] => (segments, "^", ""),
+        [idx] if idx + 1 == segments.len() => (&segments[..*idx], "^", ""),
+        [0] => (&segments[1..], "", "$"),
+        _ => {
+            return Err(invalid(
+                "template segment must be the first or the last one",
+            ))
+        }
+    };
+
+    let cell_segments = barcode_segments
+        .iter()
+        .filter(|segment| matches!(segment, Segment::Barcode(SegmentType::Cell, _)))
+        .count();
+    let mut cell_round = 0;
+    let mut names: Vec<String> = Vec::new();
+    let mut regex = prefix.to_owned();
+
+    for segment in barcode_segments {
+        match segment {
+            Segment::Barcode(segment_type, len) => {
+                let barcode_type = match segment_type {
+                    SegmentType::Cell if cell_segments > 1 => {
+                        cell_round += 1;
+                        BarcodeType::CellRound(cell_round)
+                    }
+                    SegmentType::Cell => BarcodeType::Cell,
+                    SegmentType::Umi => BarcodeType::Umi,
+                    SegmentType::Sample => BarcodeType::Sample,
+                };
+                let name = barcode_type.to_string();
+                if names.contains(&name) {
+                    return Err(invalid(&format!(
+                        "several {} segments are not supported",
+                        name
+                    )));
+                }
+                regex.push_str(&format!("(?<{}>{}{{{}}})", name, NUCLEOTIDE_REGEX, len));
+                names.push(name);
+            }
+            Segment::Skip(len) => regex.push_str(&format!("{}{{{}}}", NUCLEOTIDE_REGEX, len)),
+            Segment::Template => {}
+        }
+    }
+
+    regex.push_str(suffix);
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::error::Error;
+    use crate::notation::PatternSyntax;
+
+    #[rstest]
+    #[case("CCCCNNNN", "^(?<CB>[ATGCN]{4})(?<UMI>[ATGCN]{4})")]
+    #[case("NNNNXXXX", "^(?<UMI>[ATGCN]{4})")]
+    #[case("CCCCNNNNXX", "^(?<CB>[ATGCN]{4})(?<UMI>[ATGCN]{4})")]
+    fn test_umi_tools_pattern(#[case] pattern: &str, #[case] expected: &str) {
+        assert_eq!(expected, PatternSyntax::UmiTools.to_regex(pattern).unwrap());
+    }
+
+    #[rstest]
+    #[case("8B12M+T", "^(?<SB>[ATGCN]{8})(?<UMI>[ATGCN]{12})")]
+    #[case("5M2S+T", "^(?<UMI>[ATGCN]{5})[ATGCN]{2}")]
+    #[case("16C12M", "^(?<CB>[ATGCN]{16})(?<UMI>[ATGCN]{12})")]
+    #[case("+T8B", "(?<SB>[ATGCN]{8})$")]
+    #[case("8C4S8C+T", "^(?<CB1>[ATGCN]{8})[ATGCN]{4}(?<CB2>[ATGCN]{8})")]
+    #[case("10M90T", "^(?<UMI>[ATGCN]{10})")]
+    fn test_read_structure(#[case] pattern: &str, #[case] expected: &str) {
+        assert_eq!(
+            expected,
+            PatternSyntax::ReadStructure.to_regex(pattern).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case(PatternSyntax::UmiTools, "CCCCNNNNA")]
+    #[case(PatternSyntax::UmiTools, "NNNNCCNN")]
+    #[case(PatternSyntax::UmiTools, "XXNNNN")]
+    #[case(PatternSyntax::UmiTools, "CCCXXCCCNN")]
+    #[case(PatternSyntax::ReadStructure, "8B+M")]
+    #[case(PatternSyntax::ReadStructure, "8M+T8B")]
+    #[case(PatternSyntax::ReadStructure, "8M4M+T")]
+    #[case(PatternSyntax::ReadStructure, "0M+T")]
+    #[case(PatternSyntax::ReadStructure, "8Q")]
+    #[case(PatternSyntax::ReadStructure, "8M12")]
+    fn test_invalid_notation(#[case] syntax: PatternSyntax, #[case] pattern: &str) {
+        assert!(matches!(
+            syntax.to_regex(pattern),
+            Err(Error::InvalidPatternNotation(_, _))
+        ));
+    }
+}
@@ -1,13 +1,33 @@
 #![allow(clippy::result_large_err)]
 
-use crate::pattern::BarcodeRegex;
-use regex::bytes::Captures;
+use crate::pattern::{BarcodeCaptures, BarcodeRegex, BarcodeType};
+use crate::whitelist::{Correction, Whitelist};
 
-use seq_io::fastq::{OwnedRecord, Record, RefRecord};
+use seq_io::fastq::{OwnedRecord, Record};
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use crate::error::Error;
 
+/// Header tag of the matched pattern index (1-based), if several patterns are provided
+const PATTERN_INDEX_TAG: &str = "PI";
+
+/// Header tag of the raw (uncorrected) cell barcode, if the whitelist is provided
+pub const RAW_CELL_BARCODE_TAG: &str = "CR";
+
+/// Header tag of the read orientation matched by the pattern, if reverse complement is searched
+const ORIENTATION_TAG: &str = "OR";
+
+/// Base used to pad variable-length barcodes to their max length
+const PADDING_BASE: u8 = b'N';
+
+/// Quality (Phred 0) of bases used to pad variable-length barcodes
+const PADDING_QUALITY: u8 = b'!';
+
+/// Quality placed at separator positions of combined barcodes, as in SAM `QX`/`QT` tags
+const SEPARATOR_QUALITY: u8 = b' ';
+
 /// https://www.bioinformatics.org/sms/iupac.html
 const TRANSLATION_TABLE: [u8; 256] = {
     let mut table = [b'A'; 256];
@@ -31,112 +51,684 @@ const TRANSLATION_TABLE: [u8; 256] = {
     table
 };
 
+/// Strategy to select one of the alternative barcode patterns matched a read
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PatternSelection {
+    /// The first matched pattern in the provided order
+    #[default]
+    First,
+
+    /// The pattern matched with the least number of errors in adapters
+    Best,
+}
+
+/// Order of mates in the combined barcode
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MateOrder {
+    /// Barcode of the forward read goes first
+    #[default]
+    #[value(name = "r1-r2")]
+    ForwardReverse,
+
+    /// Barcode of the reverse read goes first
+    #[value(name = "r2-r1")]
+    ReverseForward,
+}
+
+/// Which of the required patterns of mates must match to keep the pair
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchRequirement {
+    /// At least one required pattern must match
+    #[default]
+    Any,
+
+    /// All required patterns must match
+    All,
+}
+
+/// Policy of keeping pairs by matches of patterns of their mates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MatchPolicy {
+    /// Which of the required patterns must match
+    requirement: MatchRequirement,
+
+    /// Whether patterns of forward and reverse mates are optional
+    optional: [bool; 2],
+}
+
+impl MatchPolicy {
+    /// Creates `MatchPolicy` instance with optional patterns of mates (1 or 2)
+    pub fn new(requirement: MatchRequirement, optional_mates: &[u8]) -> Self {
+        Self {
+            requirement,
+            optional: [optional_mates.contains(&1), optional_mates.contains(&2)],
+        }
+    }
+
+    /// Returns mates with not matched required patterns, if the pair must be dropped.
+    /// Matches are `None` for mates without patterns. Pairs without required patterns are kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::parse::{MatchPolicy, MatchRequirement};
+    ///
+    /// let any = MatchPolicy::new(MatchRequirement::Any, &[]);
+    /// assert_eq!(None, any.get_failed_mates([Some(true), Some(false)]));
+    /// assert_eq!(Some([true, true]), any.get_failed_mates([Some(false), Some(false)]));
+    ///
+    /// let all = MatchPolicy::new(MatchRequirement::All, &[]);
+    /// assert_eq!(Some([false, true]), all.get_failed_mates([Some(true), Some(false)]));
+    /// ```
+    pub fn get_failed_mates(&self, matches: [Option<bool>; 2]) -> Option<[bool; 2]> {
+        let required = [0, 1].map(|mate| matches[mate].filter(|_| !self.optional[mate]));
+        let failed = required.map(|matched| matched == Some(false));
+        let is_kept = match self.requirement {
+            MatchRequirement::Any => {
+                required.iter().all(Option::is_none) || required.contains(&Some(true))
+            }
+            MatchRequirement::All => !failed.contains(&true),
+        };
+        (!is_kept).then_some(failed)
+    }
+}
+
+/// Orientation of the read, in which the barcode pattern is matched
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    /// Pattern is matched in the read sequence as is
+    Forward,
+
+    /// Pattern is matched in the reverse complement of the read sequence
+    ReverseComplement,
+}
+
+impl Orientation {
+    /// Returns value of the orientation header tag
+    fn symbol(&self) -> &'static [u8] {
+        match self {
+            Orientation::Forward => b"+",
+            Orientation::ReverseComplement => b"-",
+        }
+    }
+}
+
+/// Barcode sequence with base qualities, which is added to the read header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Barcode {
+    /// Name of the barcode tag
+    pub name: String,
+
+    /// Barcode sequence
+    pub seq: Vec<u8>,
+
+    /// Base qualities of the barcode (empty, if the tag has no qualities)
+    pub qual: Vec<u8>,
+}
+
+impl Barcode {
+    pub fn new(name: &str, seq: &[u8], qual: &[u8]) -> Self {
+        Self {
+            name: name.to_owned(),
+            seq: seq.to_vec(),
+            qual: qual.to_vec(),
+        }
+    }
+
+    /// Pads the barcode to the length, if it is provided
+    fn pad(mut self, len: Option<usize>) -> Self {
+        if let Some(len) = len {
+            pad_sequence(&mut self.seq, len, PADDING_BASE);
+            pad_sequence(&mut self.qual, len, PADDING_QUALITY);
+        }
+        self
+    }
+}
+
+/// Read with barcodes parsed from it
+#[derive(Clone, Debug)]
+pub struct ParsedRead {
+    /// Read record with trimmed barcodes and the original header
+    pub record: OwnedRecord,
+
+    /// Barcodes to add to the read header in order
+    pub barcodes: Vec<Barcode>,
+}
+
+impl ParsedRead {
+    /// Creates `ParsedRead` instance without barcodes
+    pub fn from_record(record: &impl Record) -> Self {
+        Self {
+            record: get_owned_record(record),
+            barcodes: Vec::new(),
+        }
+    }
+
+    /// Returns barcode with the specified name
+    pub fn get_barcode(&self, name: &str) -> Option<&Barcode> {
+        self.barcodes.iter().find(|barcode| barcode.name == name)
+    }
+
+    /// Removes barcode with the specified name and returns it
+    fn take_barcode(&mut self, name: &str) -> Option<Barcode> {
+        let idx = self
+            .barcodes
+            .iter()
+            .position(|barcode| barcode.name == name)?;
+        Some(self.barcodes.remove(idx))
+    }
+
+    /// Returns read record with barcodes added to the header
+    pub fn into_record(self) -> OwnedRecord {
+        let mut record = self.record;
+        for barcode in &self.barcodes {
+            record.head =
+                add_to_the_header(&barcode.name, &record.head, &barcode.seq, &barcode.qual);
+        }
+        record
+    }
+}
+
+/// Combines barcodes with the same name from both mates into a single barcode
+#[derive(Clone, Debug)]
+pub struct BarcodeCombiner {
+    /// Names of barcodes to combine
+    names: Vec<String>,
+
+    /// Order of mates in the combined barcode
+    order: MateOrder,
+
+    /// Separator between barcodes of mates
+    separator: String,
+}
+
+impl BarcodeCombiner {
+    /// Creates `BarcodeCombiner` instance
+    pub fn new(names: Vec<String>, order: MateOrder, separator: String) -> Self {
+        Self {
+            names,
+            order,
+            separator,
+        }
+    }
+
+    /// Returns names of barcodes to combine
+    pub fn get_names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Replaces barcodes of both mates with the combined ones. If a barcode is found
+    /// only in one of the mates, it is copied to another mate as is. Sequences are joined
+    /// with the separator and qualities are joined with spaces in place of the separator,
+    /// so that the combined sequence and qualities have the same length.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::parse::{Barcode, BarcodeCombiner, MateOrder, ParsedRead};
+    /// use seq_io::fastq::OwnedRecord;
+    ///
+    /// let record = OwnedRecord { head: b"r".to_vec(), seq: b"A".to_vec(), qual: b"I".to_vec() };
+    /// let mut read1 = ParsedRead { record: record.clone(), barcodes: vec![Barcode::new("UMI", b"AAAA", b"IIII")] };
+    /// let mut read2 = ParsedRead { record, barcodes: vec![Barcode::new("UMI", b"CCCC", b"####")] };
+    ///
+    /// let barcode_combiner = BarcodeCombiner::new(vec!["UMI".to_string()], MateOrder::ForwardReverse, "-".to_string());
+    /// barcode_combiner.combine(&mut read1, &mut read2);
+    ///
+    /// assert_eq!(b"r UMI:AAAA-CCCC:IIII ####", read1.into_record().head.as_slice());
+    /// assert_eq!(b"r UMI:AAAA-CCCC:IIII ####", read2.into_record().head.as_slice());
+    /// ```
+    pub fn combine(&self, read1: &mut ParsedRead, read2: &mut ParsedRead) {
+        for name in &self.names {
+            let barcodes = match self.order {
+                MateOrder::ForwardReverse => [read1.take_barcode(name), read2.take_barcode(name)],
+                MateOrder::ReverseForward => [read2.take_barcode(name), read1.take_barcode(name)],
+            };
+            let barcodes: Vec<Barcode> = barcodes.into_iter().flatten().collect();
+            if barcodes.is_empty() {
+                continue;
+            }
+
+            let separator = self.separator.as_bytes();
+            let combined_barcode = Barcode {
+                name: name.to_owned(),
+                seq: barcodes
+                    .iter()
+                    .map(|barcode| barcode.seq.as_slice())
+                    .collect::<Vec<_>>()
+                    .join(separator),
+                qual: barcodes
+                    .iter()
+                    .map(|barcode| barcode.qual.as_slice())
+                    .collect::<Vec<_>>()
+                    .join(vec![SEPARATOR_QUALITY; separator.len()].as_slice()),
+            };
+            read1.barcodes.push(combined_barcode.clone());
+            read2.barcodes.push(combined_barcode);
+        }
+    }
+}
+
+/// Round cell barcode (`CB1`, `CB2`, ...) captured in the read
+struct RoundBarcode<'a> {
+    /// 1-based number of the round
+    round: usize,
+
+    /// Barcode sequence
+    seq: &'a [u8],
+
+    /// Base qualities of the barcode
+    qual: &'a [u8],
+
+    /// Length to pad the barcode to, if padding is enabled
+    padded_len: Option<usize>,
+}
+
 pub struct BarcodeParser {
-    /// Prepared regex pattern to parse barcodes
-    barcode_regex: BarcodeRegex,
+    /// Prepared alternative regex patterns to parse barcodes
+    barcode_regexes: Vec<BarcodeRegex>,
+
+    /// Strategy to select the pattern, if several patterns match a read
+    pattern_selection: PatternSelection,
 
     /// If `true`, all captured patterns will not be trimmed
     skip_trimming: bool,
 
     /// If `true`, the barcode pattern will also be matched in the reverse complement sequence.
     rc_barcodes: bool,
+
+    /// Whitelist to correct cell barcodes (`CB`)
+    whitelist: Option<Arc<Whitelist>>,
+
+    /// Whitelists to correct round cell barcodes (`CB1`, `CB2`, ...) in order of rounds
+    round_whitelists: Vec<Arc<Whitelist>>,
+
+    /// If `true`, variable-length barcodes are padded to their max length
+    pad_barcodes: bool,
+
+    /// If `true`, reads matched in reverse complement are written in the pattern orientation
+    reorient: bool,
+
+    /// Number of reads matched in the forward orientation
+    forward_reads: AtomicUsize,
+
+    /// Number of reads matched in the reverse complement orientation
+    reverse_complement_reads: AtomicUsize,
 }
 
 impl BarcodeParser {
+    /// Creates `BarcodeParser` instance, if at least one barcode pattern is provided
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        barcode_regex: Option<&BarcodeRegex>,
+        barcode_regexes: &[BarcodeRegex],
+        pattern_selection: PatternSelection,
         skip_trimming: bool,
         rc_barcodes: bool,
+        whitelist: Option<Arc<Whitelist>>,
+        round_whitelists: Vec<Arc<Whitelist>>,
+        pad_barcodes: bool,
+        reorient: bool,
     ) -> Option<Self> {
+        if barcode_regexes.is_empty() {
+            return None;
+        }
         Some(BarcodeParser {
-            barcode_regex: barcode_regex?.to_owned(),
+            barcode_regexes: barcode_regexes.to_vec(),
+            pattern_selection,
             skip_trimming,
             rc_barcodes,
+            whitelist,
+            round_whitelists,
+            pad_barcodes,
+            reorient,
+            forward_reads: AtomicUsize::new(0),
+            reverse_complement_reads: AtomicUsize::new(0),
         })
     }
 
-    pub fn parse_barcodes(&self, record: &RefRecord) -> Option<OwnedRecord> {
-        let read_captures = self.barcode_regex.get_captures(record.seq());
-        let read_seq_rc: Vec<u8>;
-        let read_captures = if read_captures.is_err() && self.rc_barcodes {
-            read_seq_rc = get_reverse_complement(record.seq());
-            self.barcode_regex.get_captures(&read_seq_rc)
-        } else {
-            read_captures
-        };
-        self.create_read(read_captures.map(Some), record)
+    /// Parses barcodes from the read. If the pattern is not matched and reverse complement
+    /// search is enabled, barcodes are parsed from the reverse complement of the read.
+    /// Such read is trimmed in the pattern orientation and then returned back to
+    /// the original orientation, unless reorienting is enabled.
+    pub fn parse_barcodes(&self, record: &impl Record) -> Option<ParsedRead> {
+        let read_captures = self.find_captures(record.seq(), record.qual());
+        if read_captures.is_ok() || !self.rc_barcodes {
+            let new_read = self.create_read(read_captures.map(Some), record)?;
+            return Some(self.add_orientation(new_read, Orientation::Forward));
+        }
+
+        let rc_record = get_reverse_complement_record(record);
+        let rc_captures = self.find_captures(rc_record.seq(), rc_record.qual());
+        let mut new_read = self.create_read(rc_captures.map(Some), &rc_record)?;
+        if !self.reorient {
+            new_read.record = get_reverse_complement_record(&new_read.record);
+        }
+        Some(self.add_orientation(new_read, Orientation::ReverseComplement))
     }
 
-    fn create_read(
+    /// Counts the read orientation and adds it to the header, if reverse complement is searched
+    fn add_orientation(&self, mut new_read: ParsedRead, orientation: Orientation) -> ParsedRead {
+        if self.rc_barcodes {
+            let counter = match orientation {
+                Orientation::Forward => &self.forward_reads,
+                Orientation::ReverseComplement => &self.reverse_complement_reads,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            new_read
+                .barcodes
+                .push(Barcode::new(ORIENTATION_TAG, orientation.symbol(), &[]));
+        }
+        new_read
+    }
+
+    /// Returns names of barcodes captured by the patterns, as they are added to headers.
+    /// Round cell barcodes are joined into the cell barcode (`CB`).
+    pub fn get_barcode_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for barcode_type in self
+            .barcode_regexes
+            .iter()
+            .flat_map(BarcodeRegex::get_barcode_types)
+        {
+            let name = match barcode_type {
+                BarcodeType::CellRound(_) => BarcodeType::Cell.to_string(),
+                barcode_type => barcode_type.to_string(),
+            };
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Returns summary of orientations, in which the pattern is matched, e.g. for `Reads`
+    pub fn summary(&self, name: &str) -> String {
+        let forward = self.forward_reads.load(Ordering::Relaxed);
+        let reverse_complement = self.reverse_complement_reads.load(Ordering::Relaxed);
+        let total = (forward + reverse_complement).max(1) as f64;
+
+        format!(
+            "{}: {} forward ({:.2}%), {} reverse complement ({:.2}%)",
+            name,
+            forward,
+            100.0 * forward as f64 / total,
+            reverse_complement,
+            100.0 * reverse_complement as f64 / total,
+        )
+    }
+
+    /// Matches barcode patterns to the read sequence.
+    /// Returns index of the selected pattern and its captures.
+    fn find_captures(
         &self,
-        read_captures: Result<Option<Captures>, Error>,
-        record: &RefRecord,
-    ) -> Option<seq_io::fastq::OwnedRecord> {
-        match (read_captures, self.skip_trimming) {
-            (Ok(Some(captures)), true) => {
-                Some(self.create_read_with_new_header(&captures, record).ok()?)
+        read_seq: &[u8],
+        read_qual: &[u8],
+    ) -> Result<(usize, BarcodeCaptures), Error> {
+        let mut best_captures: Option<(usize, BarcodeCaptures)> = None;
+
+        for (pattern_idx, barcode_regex) in self.barcode_regexes.iter().enumerate() {
+            let Ok(captures) = barcode_regex.get_captures(read_seq, read_qual) else {
+                continue;
+            };
+            if self.pattern_selection == PatternSelection::First {
+                return Ok((pattern_idx, captures));
             }
-            (Ok(Some(captures)), false) => {
-                let new_read = self.create_read_with_new_header(&captures, record).ok()?;
-                Some(trim_adapters(captures, &new_read).ok()?)
+            if best_captures
+                .as_ref()
+                .map_or(true, |(_, best)| captures.get_errors() < best.get_errors())
+            {
+                best_captures = Some((pattern_idx, captures));
             }
-            (Ok(None), _) => Some(OwnedRecord {
-                head: record.head().to_vec(),
-                seq: record.seq().to_vec(),
-                qual: record.qual().to_vec(),
+        }
+
+        best_captures.ok_or(Error::PatternNotMatched)
+    }
+
+    fn create_read(
+        &self,
+        read_captures: Result<Option<(usize, BarcodeCaptures)>, Error>,
+        record: &impl Record,
+    ) -> Option<ParsedRead> {
+        match (read_captures, self.skip_trimming) {
+            (Ok(Some((pattern_idx, captures))), true) => Some(ParsedRead {
+                barcodes: self.get_barcodes(pattern_idx, &captures, record).ok()?,
+                record: get_owned_record(record),
             }),
+            (Ok(Some((pattern_idx, captures))), false) => Some(ParsedRead {
+                barcodes: self.get_barcodes(pattern_idx, &captures, record).ok()?,
+                record: trim_adapters(captures, &get_owned_record(record)).ok()?,
+            }),
+            (Ok(None), _) => Some(ParsedRead::from_record(record)),
             (Err(_), _) => None,
         }
     }
 
-    fn create_read_with_new_header(
+    /// Returns the length, to which the barcode is padded, if padding is enabled
+    /// and the barcode capture group has the max length
+    fn get_padded_len(&self, pattern_idx: usize, barcode_name: &str) -> Option<usize> {
+        self.pad_barcodes
+            .then(|| self.barcode_regexes[pattern_idx].get_max_len(barcode_name))
+            .flatten()
+    }
+
+    /// Returns the cell barcode (`CB`) corrected against the whitelist and the raw one (`CR`),
+    /// if it is corrected against any whitelist. Barcodes not assigned to the whitelist
+    /// are kept only as the raw one.
+    fn get_cell_barcodes(&self, raw_seq: &[u8], seq: Vec<u8>, qual: &[u8]) -> Vec<Barcode> {
+        let cell_name = BarcodeType::Cell.to_string();
+        if self.whitelist.is_none() && self.round_whitelists.is_empty() {
+            return vec![Barcode::new(&cell_name, raw_seq, qual)];
+        }
+
+        let corrected_seq = match &self.whitelist {
+            Some(whitelist) => match whitelist.correct(&seq, qual) {
+                Correction::Valid => Some(seq),
+                Correction::Corrected(corrected_seq) => Some(corrected_seq),
+                Correction::Invalid => None,
+            },
+            None => Some(seq),
+        };
+
+        let mut barcodes = vec![Barcode::new(RAW_CELL_BARCODE_TAG, raw_seq, qual)];
+        if let Some(corrected_seq) = corrected_seq {
+            barcodes.push(Barcode::new(&cell_name, &corrected_seq, qual));
+        }
+        barcodes
+    }
+
+    /// Concatenates round cell barcodes in order of rounds. Each round is corrected against
+    /// its own whitelist, if round whitelists are provided, and then padded to the length.
+    /// Returns the raw cell barcode and the corrected sequence.
+    fn join_rounds(&self, mut rounds: Vec<RoundBarcode>) -> Result<(Barcode, Vec<u8>), Error> {
+        rounds.sort_by_key(|round_barcode| round_barcode.round);
+
+        let mut raw_seq = Vec::new();
+        let mut seq = Vec::new();
+        let mut qual = Vec::new();
+        let mut unassigned_round = None;
+
+        for round_barcode in rounds {
+            let padded_len = raw_seq.len() + round_barcode.padded_len.unwrap_or(0);
+            raw_seq.extend_from_slice(round_barcode.seq);
+            qual.extend_from_slice(round_barcode.qual);
+
+            // All rounds are corrected to count assignment rates of each of them
+            match self
+                .round_whitelists
+                .get(round_barcode.round - 1)
+                .map(|whitelist| whitelist.correct(round_barcode.seq, round_barcode.qual))
+            {
+                Some(Correction::Corrected(corrected_seq)) => seq.extend(corrected_seq),
+                Some(Correction::Invalid) => {
+                    unassigned_round.get_or_insert(round_barcode.round);
+                }
+                Some(Correction::Valid) | None => seq.extend_from_slice(round_barcode.seq),
+            }
+
+            pad_sequence(&mut raw_seq, padded_len, PADDING_BASE);
+            pad_sequence(&mut seq, padded_len, PADDING_BASE);
+            pad_sequence(&mut qual, padded_len, PADDING_QUALITY);
+        }
+
+        match unassigned_round {
+            Some(round) => Err(Error::UnassignedRoundBarcode(round)),
+            None => Ok((
+                Barcode {
+                    name: BarcodeType::Cell.to_string(),
+                    seq: raw_seq,
+                    qual,
+                },
+                seq,
+            )),
+        }
+    }
+
+    /// Returns barcodes captured by the pattern in order of the header tags
+    fn get_barcodes(
         &self,
-        captures: &Captures,
-        record: &RefRecord,
-    ) -> Result<OwnedRecord, Error> {
-        let mut head = record.head().to_vec();
-        let seq = record.seq().to_vec();
-        let qual = record.qual().to_vec();
-
-        for barcode in &self.barcode_regex.get_barcode_types() {
+        pattern_idx: usize,
+        captures: &BarcodeCaptures,
+        record: &impl Record,
+    ) -> Result<Vec<Barcode>, Error> {
+        let mut barcodes = Vec::new();
+        let mut rounds = Vec::new();
+        let seq = record.seq();
+        let qual = record.qual();
+
+        for barcode in &self.barcode_regexes[pattern_idx].get_barcode_types() {
             let barcode_name = barcode.to_string();
             let (barcode_start, barcode_end) =
                 get_barcode_match_positions(&barcode_name, captures)?;
-            head = add_to_the_header(
-                &barcode_name,
-                &head,
-                &seq,
-                &qual,
-                barcode_start,
-                barcode_end,
-            )?;
+            let barcode_seq = &seq[barcode_start..barcode_end];
+            let barcode_qual = &qual[barcode_start..barcode_end];
+            let padded_len = self.get_padded_len(pattern_idx, &barcode_name);
+
+            match barcode {
+                BarcodeType::Cell => barcodes.extend(
+                    self.get_cell_barcodes(barcode_seq, barcode_seq.to_vec(), barcode_qual)
+                        .into_iter()
+                        .map(|barcode| barcode.pad(padded_len)),
+                ),
+                BarcodeType::CellRound(round) => rounds.push(RoundBarcode {
+                    round: *round,
+                    seq: barcode_seq,
+                    qual: barcode_qual,
+                    padded_len,
+                }),
+                _ => barcodes
+                    .push(Barcode::new(&barcode_name, barcode_seq, barcode_qual).pad(padded_len)),
+            }
         }
 
-        Ok(OwnedRecord { head, seq, qual })
+        if !rounds.is_empty() {
+            let (raw_barcode, seq) = self.join_rounds(rounds)?;
+            barcodes.extend(self.get_cell_barcodes(&raw_barcode.seq, seq, &raw_barcode.qual));
+        }
+
+        // Record which of the alternative patterns matched the read
+        if self.barcode_regexes.len() > 1 {
+            barcodes.push(Barcode::new(
+                PATTERN_INDEX_TAG,
+                (pattern_idx + 1).to_string().as_bytes(),
+                &[],
+            ));
+        }
+
+        Ok(barcodes)
+    }
+}
+
+/// Parser of barcodes from the index read (I1/I2), which is not written to the output
+pub enum IndexParser {
+    /// Barcodes are captured by the patterns
+    Patterns(BarcodeParser),
+
+    /// The whole index read sequence is copied into the tag
+    Tag(String),
+}
+
+impl IndexParser {
+    /// Returns names of barcodes of the index read, as they are added to headers
+    pub fn get_barcode_names(&self) -> Vec<String> {
+        match self {
+            IndexParser::Patterns(barcode_parser) => barcode_parser.get_barcode_names(),
+            IndexParser::Tag(tag) => vec![tag.clone()],
+        }
+    }
+
+    /// Returns barcodes of the index read, if they are found
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::parse::{Barcode, IndexParser};
+    /// use seq_io::fastq::OwnedRecord;
+    ///
+    /// let record = OwnedRecord { head: b"r".to_vec(), seq: b"ACGT".to_vec(), qual: b"IIII".to_vec() };
+    /// let index_parser = IndexParser::Tag("BC".to_string());
+    ///
+    /// assert_eq!(Some(vec![Barcode::new("BC", b"ACGT", b"IIII")]), index_parser.parse_barcodes(&record));
+    /// ```
+    pub fn parse_barcodes(&self, record: &impl Record) -> Option<Vec<Barcode>> {
+        match self {
+            // Orientation of index reads is not added to headers of input reads
+            IndexParser::Patterns(barcode_parser) => {
+                barcode_parser.parse_barcodes(record).map(|parsed_read| {
+                    parsed_read
+                        .barcodes
+                        .into_iter()
+                        .filter(|barcode| barcode.name != ORIENTATION_TAG)
+                        .collect()
+                })
+            }
+            IndexParser::Tag(tag) => Some(vec![Barcode::new(tag, record.seq(), record.qual())]),
+        }
+    }
+}
+
+/// Pads the sequence to the length, if it is shorter
+fn pad_sequence(seq: &mut Vec<u8>, len: usize, padding: u8) {
+    if seq.len() < len {
+        seq.resize(len, padding);
+    }
+}
+
+/// Returns reverse complement of the read with reversed qualities
+fn get_reverse_complement_record(record: &impl Record) -> OwnedRecord {
+    OwnedRecord {
+        head: record.head().to_vec(),
+        seq: get_reverse_complement(record.seq()),
+        qual: record.qual().iter().rev().copied().collect(),
     }
 }
 
-fn get_full_match_positions(captures: &Captures) -> Result<(usize, usize), Error> {
-    let full_match = captures
-        .get(0)
-        .ok_or(Error::BarcodeCaptureGroupNotFound("0".to_owned()))?;
+fn get_owned_record(record: &impl Record) -> OwnedRecord {
+    OwnedRecord {
+        head: record.head().to_vec(),
+        seq: record.seq().to_vec(),
+        qual: record.qual().to_vec(),
+    }
+}
 
-    Ok((full_match.start(), full_match.end()))
+fn get_full_match_positions(captures: &BarcodeCaptures) -> (usize, usize) {
+    let full_match = captures.get_match();
+
+    (full_match.start, full_match.end)
 }
 
 fn get_barcode_match_positions(
     barcode_name: &str,
-    captures: &Captures,
+    captures: &BarcodeCaptures,
 ) -> Result<(usize, usize), Error> {
-    let full_match = captures
+    let barcode_match = captures
         .name(barcode_name)
         .ok_or(Error::BarcodeCaptureGroupNotFound(barcode_name.to_string()))?;
 
-    Ok((full_match.start(), full_match.end()))
+    Ok((barcode_match.start, barcode_match.end))
 }
 
-fn trim_adapters(captures: Captures, record: &OwnedRecord) -> Result<OwnedRecord, Error> {
-    let (start, end) = get_full_match_positions(&captures)?;
+fn trim_adapters(captures: BarcodeCaptures, record: &OwnedRecord) -> Result<OwnedRecord, Error> {
+    let (start, end) = get_full_match_positions(&captures);
     let seq = [&record.seq()[..start], &record.seq()[end..]].concat();
     let qual = [&record.qual()[..start], &record.qual()[end..]].concat();
 
@@ -147,27 +739,27 @@ fn trim_adapters(captures: Captures, record: &OwnedRecord) -> Result<OwnedRecord
     })
 }
 
+/// Adds the barcode tag to the read header, base qualities are omitted if they are empty
 fn add_to_the_header(
     barcode_type: &str,
     head: &[u8],
-    seq: &[u8],
-    qual: &[u8],
-    start: usize,
-    end: usize,
-) -> Result<Vec<u8>, Error> {
-    let barcode_seq = &seq[start..end];
-    let barcode_qual = &qual[start..end];
-
+    barcode_seq: &[u8],
+    barcode_qual: &[u8],
+) -> Vec<u8> {
     let mut result = Vec::with_capacity(
         head.len() + barcode_type.len() + barcode_seq.len() + barcode_qual.len() + 3,
     );
     result.extend_from_slice(head);
-    result.extend_from_slice(
-        format!(" {}:{}:", barcode_type, std::str::from_utf8(barcode_seq)?).as_bytes(),
-    );
-    result.extend_from_slice(barcode_qual);
+    result.push(b' ');
+    result.extend_from_slice(barcode_type.as_bytes());
+    result.push(b':');
+    result.extend_from_slice(barcode_seq);
+    if !barcode_qual.is_empty() {
+        result.push(b':');
+        result.extend_from_slice(barcode_qual);
+    }
 
-    Ok(result)
+    result
 }
 
 pub fn get_reverse_complement(sequence: &[u8]) -> Vec<u8> {
@@ -180,9 +772,36 @@ pub fn get_reverse_complement(sequence: &[u8]) -> Vec<u8> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use rstest::rstest;
+    use seq_io::fastq::{OwnedRecord, Reader};
+
+    use crate::parse::{
+        get_reverse_complement, Barcode, BarcodeCombiner, BarcodeParser, MatchPolicy,
+        MatchRequirement, MateOrder, ParsedRead, PatternSelection,
+    };
+    use crate::pattern::{BarcodeRegex, MaxError};
+    use crate::whitelist::Whitelist;
 
-    use crate::parse::get_reverse_complement;
+    #[rstest]
+    #[case(MatchRequirement::Any, &[], [Some(true), Some(false)], None)]
+    #[case(MatchRequirement::Any, &[], [Some(false), None], Some([true, false]))]
+    #[case(MatchRequirement::Any, &[], [None, None], None)]
+    #[case(MatchRequirement::Any, &[2], [Some(false), Some(true)], Some([true, false]))]
+    #[case(MatchRequirement::All, &[], [Some(true), None], None)]
+    #[case(MatchRequirement::All, &[], [Some(false), Some(false)], Some([true, true]))]
+    #[case(MatchRequirement::All, &[2], [Some(true), Some(false)], None)]
+    #[case(MatchRequirement::All, &[1, 2], [Some(false), Some(false)], None)]
+    fn test_get_failed_mates(
+        #[case] requirement: MatchRequirement,
+        #[case] optional_mates: &[u8],
+        #[case] matches: [Option<bool>; 2],
+        #[case] expected: Option<[bool; 2]>,
+    ) {
+        let match_policy = MatchPolicy::new(requirement, optional_mates);
+        assert_eq!(expected, match_policy.get_failed_mates(matches));
+    }
 
     #[rstest]
     #[case(b"", b"")]
@@ -194,4 +813,285 @@ mod tests {
     fn test_get_reverse_complement(#[case] sequence: &[u8], #[case] rc_sequence: &[u8]) {
         assert_eq!(get_reverse_complement(sequence), rc_sequence);
     }
+
+    #[rstest]
+    #[case(b"ATGCAAAACCGG", false, b"r UMI:AAAA:EFGH OR:+", b"CCGG", b"IJKL")]
+    #[case(b"ACGGTTTTGCAT", false, b"r UMI:AAAA:HGFE OR:-", b"ACGG", b"ABCD")]
+    #[case(b"ACGGTTTTGCAT", true, b"r UMI:AAAA:HGFE OR:-", b"CCGT", b"DCBA")]
+    fn test_parse_barcodes_in_reverse_complement(
+        #[case] read_seq: &[u8],
+        #[case] reorient: bool,
+        #[case] expected_head: &[u8],
+        #[case] expected_seq: &[u8],
+        #[case] expected_qual: &[u8],
+    ) {
+        let barcode_regexes = vec![BarcodeRegex::new(
+            "^atgc(?<UMI>[ATGCN]{4})",
+            MaxError::Count(0),
+            false,
+            None,
+            None,
+        )
+        .unwrap()];
+        let barcode_parser = BarcodeParser::new(
+            &barcode_regexes,
+            PatternSelection::First,
+            false,
+            true,
+            None,
+            Vec::new(),
+            false,
+            reorient,
+        )
+        .unwrap();
+
+        let new_record = parse_read(&barcode_parser, read_seq, b"ABCDEFGHIJKL").unwrap();
+        assert_eq!(expected_head, &new_record.head[..]);
+        assert_eq!(expected_seq, &new_record.seq[..]);
+        assert_eq!(expected_qual, &new_record.qual[..]);
+    }
+
+    #[rstest]
+    #[case(PatternSelection::First, b"ATCCAAAATTTTGG", b"r UMI:AAAA:IIII PI:1")]
+    #[case(PatternSelection::Best, b"ATCCAAAATTTTGG", b"r UMI:TTTT:IIII PI:2")]
+    #[case(PatternSelection::Best, b"ATGCAAAATTTTGG", b"r UMI:AAAA:IIII PI:1")]
+    #[case(PatternSelection::First, b"CCCCAAAATTTTGG", b"r UMI:TTTT:IIII PI:2")]
+    fn test_parse_barcodes_with_several_patterns(
+        #[case] pattern_selection: PatternSelection,
+        #[case] read_seq: &[u8],
+        #[case] expected_head: &[u8],
+    ) {
+        let barcode_regexes = vec![
+            BarcodeRegex::new(
+                "^atgc(?<UMI>[ATGCN]{4})",
+                MaxError::Count(1),
+                false,
+                None,
+                None,
+            )
+            .unwrap(),
+            BarcodeRegex::new(
+                "aaaa(?<UMI>[ATGCN]{4})gg",
+                MaxError::Count(1),
+                false,
+                None,
+                None,
+            )
+            .unwrap(),
+        ];
+        let barcode_parser = BarcodeParser::new(
+            &barcode_regexes,
+            pattern_selection,
+            true,
+            false,
+            None,
+            Vec::new(),
+            false,
+            false,
+        )
+        .unwrap();
+
+        let new_record = parse_read(&barcode_parser, read_seq, &vec![b'I'; read_seq.len()]);
+        assert_eq!(expected_head, &new_record.unwrap().head[..]);
+    }
+
+    #[rstest]
+    #[case(b"ACGTAAAA", b"IIIIIIII", b"r CR:ACGT:IIII CB:ACGT:IIII UMI:AAAA:IIII")]
+    #[case(b"ACCTAAAA", b"II#IIIII", b"r CR:ACCT:II#I CB:ACGT:II#I UMI:AAAA:IIII")]
+    #[case(b"ACGAAAAA", b"III#IIII", b"r CR:ACGA:III# CB:ACGT:III# UMI:AAAA:IIII")]
+    #[case(b"ACGAAAAA", b"IIIIIIII", b"r CR:ACGA:IIII UMI:AAAA:IIII")]
+    fn test_parse_barcodes_with_whitelist(
+        #[case] read_seq: &[u8],
+        #[case] read_qual: &[u8],
+        #[case] expected_head: &[u8],
+    ) {
+        let barcode_regexes = vec![BarcodeRegex::new(
+            "^(?<CB>[ATGCN]{4})(?<UMI>[ATGCN]{4})",
+            MaxError::Count(1),
+            false,
+            None,
+            None,
+        )
+        .unwrap()];
+        let whitelist = Whitelist::new(["ACGT", "TCGA"]);
+        let barcode_parser = BarcodeParser::new(
+            &barcode_regexes,
+            PatternSelection::First,
+            true,
+            false,
+            Some(Arc::new(whitelist)),
+            Vec::new(),
+            false,
+            false,
+        )
+        .unwrap();
+
+        let new_record = parse_read(&barcode_parser, read_seq, read_qual);
+        assert_eq!(expected_head, &new_record.unwrap().head[..]);
+    }
+
+    #[rstest]
+    #[case(b"AAAGGCCCTTTT", b"IIIIIIIIIIII", Some(b"r UMI:TTTT:IIII CR:CCCAAA:IIIIII CB:CCCAAA:IIIIII".as_slice()))]
+    #[case(b"AACGGCGCTTTT", b"II#III#IIIII", Some(b"r UMI:TTTT:IIII CR:CGCAAC:I#III# CB:CCCAAA:I#III#".as_slice()))]
+    #[case(b"AAAGGGGGTTTT", b"IIIIIIIIIIII", None)]
+    #[case(b"CCCGGCCCTTTT", b"IIIIIIIIIIII", None)]
+    fn test_parse_barcodes_with_rounds(
+        #[case] read_seq: &[u8],
+        #[case] read_qual: &[u8],
+        #[case] expected_head: Option<&[u8]>,
+    ) {
+        let barcode_regexes = vec![BarcodeRegex::new(
+            "^(?<CB2>[ATGCN]{3})gg(?<CB1>[ATGCN]{3})(?<UMI>[ATGCN]{4})",
+            MaxError::Count(0),
+            false,
+            None,
+            None,
+        )
+        .unwrap()];
+        let round_whitelists = vec![
+            Arc::new(Whitelist::new(["CCC", "TTT"])),
+            Arc::new(Whitelist::new(["AAA", "GTG"])),
+        ];
+        let barcode_parser = BarcodeParser::new(
+            &barcode_regexes,
+            PatternSelection::First,
+            true,
+            false,
+            None,
+            round_whitelists,
+            false,
+            false,
+        )
+        .unwrap();
+
+        let new_record = parse_read(&barcode_parser, read_seq, read_qual);
+        assert_eq!(
+            expected_head,
+            new_record.as_ref().map(|record| &record.head[..])
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "^(?<CB>[ATGCN]{3,5})aaccggtt(?<UMI>[ATGCN]{2})",
+        b"TTTAACCGGTTCC",
+        b"r CB:TTTNN:III!! UMI:CC:II"
+    )]
+    #[case(
+        "^(?<CB>[ATGCN]{3,5})aaccggtt(?<UMI>[ATGCN]{2})",
+        b"TTTTTAACCGGTTCC",
+        b"r CB:TTTTT:IIIII UMI:CC:II"
+    )]
+    #[case(
+        "^(?<CB2>[ATGCN]{2,3})aaccggtt(?<CB1>[ATGCN]{2})",
+        b"TTAACCGGTTCC",
+        b"r CB:CCTTN:IIII!"
+    )]
+    fn test_parse_barcodes_with_padding(
+        #[case] pattern: &str,
+        #[case] read_seq: &[u8],
+        #[case] expected_head: &[u8],
+    ) {
+        let barcode_regexes =
+            vec![BarcodeRegex::new(pattern, MaxError::Count(1), false, None, None).unwrap()];
+        let barcode_parser = BarcodeParser::new(
+            &barcode_regexes,
+            PatternSelection::First,
+            true,
+            false,
+            None,
+            Vec::new(),
+            true,
+            false,
+        )
+        .unwrap();
+
+        let new_record = parse_read(&barcode_parser, read_seq, &vec![b'I'; read_seq.len()]);
+        assert_eq!(expected_head, &new_record.unwrap().head[..]);
+    }
+
+    #[rstest]
+    #[case(MateOrder::ForwardReverse, Some(b"AA".as_slice()), Some(b"CC".as_slice()), b"r UMI:AA+CC:II ##".as_slice())]
+    #[case(MateOrder::ReverseForward, Some(b"AA".as_slice()), Some(b"CC".as_slice()), b"r UMI:CC+AA:## II".as_slice())]
+    #[case(MateOrder::ForwardReverse, None, Some(b"CC".as_slice()), b"r UMI:CC:##".as_slice())]
+    #[case(MateOrder::ForwardReverse, None, None, b"r".as_slice())]
+    fn test_combine_barcodes(
+        #[case] order: MateOrder,
+        #[case] umi1: Option<&[u8]>,
+        #[case] umi2: Option<&[u8]>,
+        #[case] expected_head: &[u8],
+    ) {
+        let record = OwnedRecord {
+            head: b"r".to_vec(),
+            seq: b"A".to_vec(),
+            qual: b"I".to_vec(),
+        };
+        let mut read1 = ParsedRead {
+            record: record.clone(),
+            barcodes: umi1.map_or(vec![], |umi| vec![Barcode::new("UMI", umi, b"II")]),
+        };
+        let mut read2 = ParsedRead {
+            record,
+            barcodes: umi2.map_or(vec![], |umi| vec![Barcode::new("UMI", umi, b"##")]),
+        };
+
+        let barcode_combiner =
+            BarcodeCombiner::new(vec!["UMI".to_string()], order, "+".to_string());
+        barcode_combiner.combine(&mut read1, &mut read2);
+
+        assert_eq!(expected_head, read1.into_record().head.as_slice());
+        assert_eq!(expected_head, read2.into_record().head.as_slice());
+    }
+
+    #[rstest]
+    #[case("-")]
+    #[case("+")]
+    #[case("")]
+    fn test_combine_barcodes_qualities(#[case] separator: &str) {
+        let record = OwnedRecord {
+            head: b"r".to_vec(),
+            seq: b"A".to_vec(),
+            qual: b"I".to_vec(),
+        };
+        let mut read1 = ParsedRead {
+            record: record.clone(),
+            barcodes: vec![Barcode::new("UMI", b"AAAA", b"IIII")],
+        };
+        let mut read2 = ParsedRead {
+            record,
+            barcodes: vec![Barcode::new("UMI", b"CCC", b"-#-")],
+        };
+
+        let barcode_combiner = BarcodeCombiner::new(
+            vec!["UMI".to_string()],
+            MateOrder::ForwardReverse,
+            separator.to_string(),
+        );
+        barcode_combiner.combine(&mut read1, &mut read2);
+
+        let combined_barcode = read1.get_barcode("UMI").unwrap();
+        assert_eq!(
+            [b"AAAA".as_slice(), b"CCC"].join(separator.as_bytes()),
+            combined_barcode.seq
+        );
+        assert_eq!(
+            [b"IIII".as_slice(), b"-#-"].join(vec![b' '; separator.len()].as_slice()),
+            combined_barcode.qual
+        );
+        assert_eq!(combined_barcode.seq.len(), combined_barcode.qual.len());
+    }
+
+    fn parse_read(
+        barcode_parser: &BarcodeParser,
+        read_seq: &[u8],
+        read_qual: &[u8],
+    ) -> Option<OwnedRecord> {
+        let fastq = [b"@r\n", read_seq, b"\n+\n", read_qual, b"\n"].concat();
+        let mut reader = Reader::new(fastq.as_slice());
+        let record = reader.next().unwrap().unwrap();
+
+        barcode_parser
+            .parse_barcodes(&record)
+            .map(|parsed_read| parsed_read.into_record())
+    }
 }
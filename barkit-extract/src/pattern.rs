@@ -1,44 +1,248 @@
 #![allow(clippy::result_large_err)]
 
-use std::{fmt, mem::size_of};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    mem::size_of,
+    ops::Range,
+    str::FromStr,
+};
 
 use fancy_regex::Regex as FancyRegex;
 use regex::bytes::{Captures, Regex};
+use regex_syntax::hir::{Hir, HirKind};
 
 use crate::error::Error;
+use crate::matcher::{get_iupac_bases, AdapterMatch, AdapterMatcher, MAX_ADAPTER_LENGTH};
 
 const FUZZY_CHARACTER: &str = ".";
-const ADAPTER_PATTERN_REGEX: &str = r"(?<!\[)\b[atgcryswkmbdhvn]+\b(?!\])";
+const ADAPTER_PATTERN_REGEX: &str = r"(?<![\[<])\b[atgcryswkmbdhvn]+\b(?![\]>])";
+const ANNOTATION_PATTERN_REGEX: &str = r"\{([a-z]+)=([^}]*)\}";
+
+/// Reads barcode patterns from the file (one per line). Empty lines and lines
+/// starting with `#` are skipped.
+pub fn read_patterns(path: &str) -> Result<Vec<String>, Error> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Max number of errors in an adapter sequence
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaxError {
+    /// Absolute number of errors
+    Count(usize),
+
+    /// Number of errors per adapter nucleotide
+    Rate(f64),
+}
+
+impl MaxError {
+    /// Returns max number of errors for the adapter of the specified length
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::pattern::MaxError;
+    ///
+    /// assert_eq!(2, MaxError::Count(2).resolve(30));
+    /// assert_eq!(3, MaxError::Rate(0.1).resolve(30));
+    /// assert_eq!(0, MaxError::Rate(0.1).resolve(4));
+    /// ```
+    pub fn resolve(&self, adapter_len: usize) -> usize {
+        match self {
+            MaxError::Count(max_error) => *max_error,
+            MaxError::Rate(rate) => (rate * adapter_len as f64).floor() as usize,
+        }
+    }
+}
+
+/// Part of the read sequence, where barcode pattern is searched.
+/// Negative positions are counted from the end of the read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchWindow {
+    /// Start position of the window (inclusive)
+    start: isize,
+
+    /// End position of the window (exclusive), `None` means the end of the read
+    end: Option<isize>,
+}
+
+impl SearchWindow {
+    /// Returns positions of the window in the read of the specified length
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::pattern::SearchWindow;
+    ///
+    /// assert_eq!(0..40, "0..40".parse::<SearchWindow>().unwrap().resolve(150));
+    /// assert_eq!(120..150, "-30..".parse::<SearchWindow>().unwrap().resolve(150));
+    /// assert_eq!(0..20, "..40".parse::<SearchWindow>().unwrap().resolve(20));
+    /// ```
+    pub fn resolve(&self, read_len: usize) -> Range<usize> {
+        let get_position = |position: isize| match position {
+            position if position < 0 => read_len.saturating_sub(position.unsigned_abs()),
+            position => read_len.min(position as usize),
+        };
+        let start = get_position(self.start);
+        let end = self.end.map_or(read_len, get_position).max(start);
+        start..end
+    }
+}
+
+impl FromStr for SearchWindow {
+    type Err = Error;
+
+    /// Parses the window in the `<start>..<end>` format, where both positions are optional
+    fn from_str(window: &str) -> Result<Self, Error> {
+        let (start, end) = window
+            .split_once("..")
+            .ok_or(Error::InvalidSearchWindow(window.to_owned()))?;
+        let parse_position = |position: &str| {
+            position
+                .parse::<isize>()
+                .map_err(|_| Error::InvalidSearchWindow(window.to_owned()))
+        };
+
+        Ok(Self {
+            start: match start.trim() {
+                "" => 0,
+                start => parse_position(start)?,
+            },
+            end: match end.trim() {
+                "" => None,
+                end => Some(parse_position(end)?),
+            },
+        })
+    }
+}
 
 pub struct BarcodePattern {
     adapter_pattern: FancyRegex,
+
+    /// Barcode pattern without inline annotations
     barcode_pattern: String,
-    max_error: usize,
+
+    /// Default max error of adapters
+    max_error: MaxError,
+
+    /// Max errors of adapters with inline annotation (e.g. `atgc{e=2}`) by their start positions
+    adapter_max_errors: HashMap<usize, MaxError>,
+
+    /// If `true`, insertions and deletions are also counted as errors
+    indels: bool,
 }
 
 impl BarcodePattern {
-    pub fn new(pattern: &str, max_error: &usize) -> Result<Self, Error> {
+    pub fn new(pattern: &str, max_error: &MaxError, indels: bool) -> Result<Self, Error> {
+        let adapter_pattern = FancyRegex::new(ADAPTER_PATTERN_REGEX)?;
+        let (barcode_pattern, adapter_max_errors) =
+            Self::parse_annotations(pattern, &adapter_pattern)?;
         Ok(Self {
-            adapter_pattern: FancyRegex::new(ADAPTER_PATTERN_REGEX)?,
-            barcode_pattern: pattern.to_owned(),
+            adapter_pattern,
+            barcode_pattern,
             max_error: *max_error,
+            adapter_max_errors,
+            indels,
         })
     }
 
+    /// Removes inline annotations (e.g. `{e=2}` or `{r=0.1}`) from the pattern and returns
+    /// max errors of annotated adapters by their start positions in the cleaned pattern.
+    fn parse_annotations(
+        pattern: &str,
+        adapter_pattern: &FancyRegex,
+    ) -> Result<(String, HashMap<usize, MaxError>), Error> {
+        let annotation_pattern = FancyRegex::new(ANNOTATION_PATTERN_REGEX)?;
+
+        let mut adapters = HashMap::new();
+        for mat in adapter_pattern.find_iter(pattern) {
+            let mat = mat?;
+            adapters.insert(mat.end(), mat.start());
+        }
+
+        let mut cleaned_pattern = String::with_capacity(pattern.len());
+        let mut adapter_max_errors = HashMap::new();
+        let mut last_end = 0;
+
+        for captures in annotation_pattern.captures_iter(pattern) {
+            let captures = captures?;
+            let annotation = captures.get(0).ok_or(Error::PatternNotMatched)?;
+            let adapter_start = adapters
+                .get(&annotation.start())
+                .ok_or(Error::OrphanAnnotation(annotation.as_str().to_owned()))?;
+
+            let max_error = match &captures[1] {
+                "e" => captures[2].parse::<usize>().ok().map(MaxError::Count),
+                "r" => captures[2]
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|rate| (0.0..1.0).contains(rate))
+                    .map(MaxError::Rate),
+                _ => None,
+            }
+            .ok_or(Error::InvalidAnnotation(annotation.as_str().to_owned()))?;
+
+            // Shift the adapter position by the length of annotations removed before it
+            let cleaned_start = adapter_start - (last_end - cleaned_pattern.len());
+            cleaned_pattern.push_str(&pattern[last_end..annotation.start()]);
+            adapter_max_errors.insert(cleaned_start, max_error);
+            last_end = annotation.end();
+        }
+        cleaned_pattern.push_str(&pattern[last_end..]);
+
+        Ok((cleaned_pattern, adapter_max_errors))
+    }
+
+    /// Returns max error of the adapter started at the specified position
+    fn get_adapter_max_error(&self, start: usize, adapter_len: usize) -> usize {
+        self.adapter_max_errors
+            .get(&start)
+            .unwrap_or(&self.max_error)
+            .resolve(adapter_len)
+    }
+
     /// Generates sequences with errors that may occur during amplification.
     ///
     /// # Example
     ///
     /// ```
-    /// use barkit_extract::pattern::BarcodePattern;
+    /// use barkit_extract::pattern::{BarcodePattern, MaxError};
     ///
-    /// let barcode_pattern = BarcodePattern::new("^atgc(?<UMI>[ATGCN]{12})", &1).unwrap();
+    /// let barcode_pattern = BarcodePattern::new("^atgc(?<UMI>[ATGCN]{12})", &MaxError::Count(1), false).unwrap();
     ///
     /// let sequences_with_errors = barcode_pattern.get_sequence_with_errors("ATGC").unwrap();
     /// assert_eq!(vec!["ATG.", "AT.C", "A.GC", ".TGC"], sequences_with_errors);
     /// ```
     pub fn get_sequence_with_errors(&self, sequence: &str) -> Result<Vec<String>, Error> {
-        if self.max_error == 0 {
+        self.get_sequence_with_max_error(sequence, self.max_error.resolve(sequence.len()))
+    }
+
+    /// Generates sequences with up to `max_error` errors.
+    /// IUPAC codes are replaced with character classes of their nucleotides.
+    fn get_sequence_with_max_error(
+        &self,
+        sequence: &str,
+        max_error: usize,
+    ) -> Result<Vec<String>, Error> {
+        Ok(self
+            .get_cases_with_max_error(sequence, max_error)?
+            .iter()
+            .map(|case| get_iupac_regex(case))
+            .collect())
+    }
+
+    /// Generates sequences with up to `max_error` errors, where errors are replaced by `FUZZY_CHARACTER`
+    fn get_cases_with_max_error(
+        &self,
+        sequence: &str,
+        max_error: usize,
+    ) -> Result<Vec<String>, Error> {
+        if max_error == 0 {
             return Ok(vec![sequence.to_string().to_ascii_uppercase()]);
         }
 
@@ -46,7 +250,11 @@ impl BarcodePattern {
             return Ok(Vec::new());
         }
 
-        if self.max_error >= sequence.len() {
+        if self.indels {
+            return Ok(self.get_sequence_with_edits(sequence, max_error));
+        }
+
+        if max_error >= sequence.len() {
             return Ok(vec![FUZZY_CHARACTER.repeat(sequence.len())]);
         }
 
@@ -61,10 +269,9 @@ impl BarcodePattern {
 
         let upper: Vec<char> = sequence.chars().map(|c| c.to_ascii_uppercase()).collect();
 
-        for permutation_mask in 0..=max_permutation_mask {
-            if permutation_mask.count_ones() as usize != num_chars - self.max_error {
-                continue;
-            }
+        // Iterate over masks with `num_chars - max_error` set bits in the ascending order
+        let mut permutation_mask = max_permutation_mask >> max_error;
+        while permutation_mask <= max_permutation_mask {
             let mut s = String::new();
             for (idx, _) in upper.iter().enumerate().take(num_chars) {
                 if (permutation_mask & (1 << idx)) == 0 {
@@ -74,18 +281,84 @@ impl BarcodePattern {
                 }
             }
             cases.push(s);
+
+            // The next greater mask with the same number of set bits (Gosper's hack)
+            let lowest_bit = permutation_mask & permutation_mask.wrapping_neg();
+            let Some(ripple) = permutation_mask.checked_add(lowest_bit) else {
+                break;
+            };
+            permutation_mask = (((ripple ^ permutation_mask) >> 2) / lowest_bit) | ripple;
         }
         Ok(cases)
     }
 
+    /// Generates sequences within the edit distance (substitutions, insertions and deletions)
+    /// of `max_error`. Sequences with fewer edits go first, so the regex prefers the closest
+    /// alignment and capture groups keep their expected coordinates.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::pattern::{BarcodePattern, MaxError};
+    ///
+    /// let barcode_pattern = BarcodePattern::new("^at(?<UMI>[ATGCN]{12})", &MaxError::Count(1), true).unwrap();
+    ///
+    /// let sequences_with_errors = barcode_pattern.get_sequence_with_errors("AT").unwrap();
+    /// assert_eq!(
+    ///     vec!["AT", ".T", "T", ".AT", "A.", "A", "A.T", "AT."],
+    ///     sequences_with_errors
+    /// );
+    /// ```
+    fn get_sequence_with_edits(&self, sequence: &str, max_error: usize) -> Vec<String> {
+        let mut level = vec![sequence.to_ascii_uppercase()];
+        let mut seen: HashSet<String> = level.iter().cloned().collect();
+        let mut cases = level.clone();
+
+        for _ in 0..max_error {
+            let mut next_level = Vec::new();
+            for case in &level {
+                for idx in 0..=case.len() {
+                    let mut edits = Vec::with_capacity(3);
+                    if idx < case.len() {
+                        if &case[idx..idx + 1] != FUZZY_CHARACTER {
+                            edits.push(format!(
+                                "{}{}{}",
+                                &case[..idx],
+                                FUZZY_CHARACTER,
+                                &case[idx + 1..]
+                            ));
+                        }
+                        edits.push(format!("{}{}", &case[..idx], &case[idx + 1..]));
+                    }
+                    edits.push(format!(
+                        "{}{}{}",
+                        &case[..idx],
+                        FUZZY_CHARACTER,
+                        &case[idx..]
+                    ));
+
+                    for edit in edits {
+                        if seen.insert(edit.clone()) {
+                            next_level.push(edit);
+                        }
+                    }
+                }
+            }
+            cases.extend(next_level.iter().cloned());
+            level = next_level;
+        }
+
+        cases
+    }
+
     /// Returns regex pattern with PCR errors.
     ///
     /// # Example
     ///
     /// ```
-    /// use barkit_extract::pattern::BarcodePattern;
+    /// use barkit_extract::pattern::{BarcodePattern, MaxError};
     ///
-    /// let barcode_pattern = BarcodePattern::new("^atgc(?<UMI>[ATGCN]{12})", &1).unwrap();
+    /// let barcode_pattern = BarcodePattern::new("^atgc(?<UMI>[ATGCN]{12})", &MaxError::Count(1), false).unwrap();
     ///
     /// let pattern_with_pcr_errors = barcode_pattern.get_pattern_with_errors().unwrap();
     /// assert_eq!("^(ATG.|AT.C|A.GC|.TGC)(?<UMI>[ATGCN]{12})", pattern_with_pcr_errors);
@@ -98,7 +371,10 @@ impl BarcodePattern {
             let mat = mat?;
             result.push_str(&self.barcode_pattern[last_end..mat.start()]);
 
-            let fuzzy_patterns = self.get_sequence_with_errors(mat.as_str());
+            let fuzzy_patterns = self.get_sequence_with_max_error(
+                mat.as_str(),
+                self.get_adapter_max_error(mat.start(), mat.as_str().len()),
+            );
             result.push_str(&format!("({})", fuzzy_patterns?.join("|")));
 
             last_end = mat.end();
@@ -107,6 +383,164 @@ impl BarcodePattern {
         result.push_str(&self.barcode_pattern[last_end..]);
         Ok(result)
     }
+
+    /// Returns adapters in upper case with the number of their sequences with errors,
+    /// which are alternatives in the regex pattern with errors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::pattern::{BarcodePattern, MaxError};
+    ///
+    /// let barcode_pattern = BarcodePattern::new("^atgc(?<UMI>[ATGCN]{12})", &MaxError::Count(1), false).unwrap();
+    ///
+    /// assert_eq!(vec![("ATGC".to_string(), 4)], barcode_pattern.get_adapter_variants().unwrap());
+    /// ```
+    pub fn get_adapter_variants(&self) -> Result<Vec<(String, usize)>, Error> {
+        let mut variants = Vec::new();
+
+        for mat in self.adapter_pattern.find_iter(&self.barcode_pattern) {
+            let mat = mat?;
+            let sequences = self.get_sequence_with_max_error(
+                mat.as_str(),
+                self.get_adapter_max_error(mat.start(), mat.as_str().len()),
+            )?;
+            variants.push((mat.as_str().to_ascii_uppercase(), sequences.len()));
+        }
+
+        Ok(variants)
+    }
+
+    /// Returns the number of adapters in the pattern
+    fn count_adapters(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        for mat in self.adapter_pattern.find_iter(&self.barcode_pattern) {
+            mat?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Splits the pattern by adapters, which can be matched approximately on their own.
+    /// Adapters nested into groups, followed by quantifiers or located in a pattern
+    /// with top-level alternation are expanded into the regex pattern with errors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::pattern::{BarcodePattern, MaxError, PatternSegment};
+    ///
+    /// let barcode_pattern = BarcodePattern::new("^atgc(?<UMI>[ATGCN]{12})", &MaxError::Count(1), false).unwrap();
+    ///
+    /// assert_eq!(
+    ///     vec![
+    ///         PatternSegment::Regex("^".to_string()),
+    ///         PatternSegment::Adapter {
+    ///             sequence: "ATGC".to_string(),
+    ///             max_error: 1,
+    ///         },
+    ///         PatternSegment::Regex("(?<UMI>[ATGCN]{12})".to_string()),
+    ///     ],
+    ///     barcode_pattern.get_segments().unwrap()
+    /// );
+    /// ```
+    pub fn get_segments(&self) -> Result<Vec<PatternSegment>, Error> {
+        let pattern = &self.barcode_pattern;
+        let top_level = get_top_level_mask(pattern);
+        let has_alternation = pattern
+            .bytes()
+            .zip(&top_level)
+            .any(|(byte, is_top_level)| byte == b'|' && *is_top_level);
+
+        let mut segments = Vec::new();
+        let mut regex = String::new();
+        let mut last_end = 0;
+
+        for mat in self.adapter_pattern.find_iter(pattern) {
+            let mat = mat?;
+            regex.push_str(&pattern[last_end..mat.start()]);
+
+            let is_standalone = !has_alternation
+                && top_level[mat.start()]
+                && mat.as_str().len() <= MAX_ADAPTER_LENGTH
+                && !pattern[mat.end()..].starts_with(['?', '*', '+', '{']);
+
+            if is_standalone {
+                if !regex.is_empty() {
+                    segments.push(PatternSegment::Regex(std::mem::take(&mut regex)));
+                }
+                segments.push(PatternSegment::Adapter {
+                    sequence: mat.as_str().to_ascii_uppercase(),
+                    max_error: self.get_adapter_max_error(mat.start(), mat.as_str().len()),
+                });
+            } else {
+                let fuzzy_patterns = self.get_sequence_with_max_error(
+                    mat.as_str(),
+                    self.get_adapter_max_error(mat.start(), mat.as_str().len()),
+                );
+                regex.push_str(&format!("({})", fuzzy_patterns?.join("|")));
+            }
+
+            last_end = mat.end();
+        }
+
+        regex.push_str(&pattern[last_end..]);
+        if !regex.is_empty() {
+            segments.push(PatternSegment::Regex(regex));
+        }
+        Ok(segments)
+    }
+}
+
+/// Part of the barcode pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatternSegment {
+    /// Regex pattern with expanded nested adapters
+    Regex(String),
+
+    /// Adapter sequence in upper case, which is matched approximately
+    Adapter { sequence: String, max_error: usize },
+}
+
+/// Replaces IUPAC codes in the adapter sequence with character classes of their nucleotides
+fn get_iupac_regex(sequence: &str) -> String {
+    sequence
+        .bytes()
+        .map(|code| match get_iupac_bases(code) {
+            Some(bases) if bases.len() > 1 => {
+                format!(
+                    "[{}]",
+                    bases.iter().map(|base| *base as char).collect::<String>()
+                )
+            }
+            _ => (code as char).to_string(),
+        })
+        .collect()
+}
+
+/// Returns for every byte of the pattern, whether it is located outside of groups and
+/// character classes and is not escaped
+fn get_top_level_mask(pattern: &str) -> Vec<bool> {
+    let mut mask = Vec::with_capacity(pattern.len());
+    let (mut depth, mut in_class, mut escaped) = (0usize, false, false);
+
+    for byte in pattern.bytes() {
+        mask.push(depth == 0 && !in_class && !escaped);
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match byte {
+            b'\\' => escaped = true,
+            b'[' if !in_class => in_class = true,
+            b']' if in_class => in_class = false,
+            b'(' if !in_class => depth += 1,
+            b')' if !in_class => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    mask
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -119,6 +553,12 @@ pub enum BarcodeType {
 
     /// Cell barcode
     Cell,
+
+    /// Part of the cell barcode from the split-pool round (1-based), e.g. `CB1`
+    CellRound(usize),
+
+    /// Barcode with user-defined name (e.g. feature barcode or second UMI)
+    Custom(String),
 }
 
 impl BarcodeType {
@@ -133,12 +573,30 @@ impl BarcodeType {
     /// assert_eq!(BarcodeType::Umi, BarcodeType::parse_type("UMI").unwrap());
     /// assert_eq!(BarcodeType::Sample, BarcodeType::parse_type("SB").unwrap());
     /// assert_eq!(BarcodeType::Cell, BarcodeType::parse_type("CB").unwrap());
+    /// assert_eq!(BarcodeType::CellRound(2), BarcodeType::parse_type("CB2").unwrap());
+    /// assert_eq!(
+    ///     BarcodeType::Custom("FB".to_string()),
+    ///     BarcodeType::parse_type("FB").unwrap()
+    /// );
+    /// assert!(matches!(
+    ///     BarcodeType::parse_type("F.B"),
+    ///     Err(UnexpectedCaptureGroupName(_))
+    /// ));
     /// ```
     pub fn parse_type(name: &str) -> Result<Self, Error> {
         match name {
             "UMI" => Ok(BarcodeType::Umi),
             "SB" => Ok(BarcodeType::Sample),
             "CB" => Ok(BarcodeType::Cell),
+            _ if name.strip_prefix("CB").is_some_and(|round| {
+                round.parse::<usize>().is_ok_and(|round| round > 0) && !round.starts_with('0')
+            }) =>
+            {
+                Ok(BarcodeType::CellRound(name[2..].parse().unwrap()))
+            }
+            _ if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                Ok(BarcodeType::Custom(name.to_owned()))
+            }
             _ => Err(Error::UnexpectedCaptureGroupName(name.to_owned())),
         }
     }
@@ -146,25 +604,151 @@ impl BarcodeType {
 
 impl fmt::Display for BarcodeType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                BarcodeType::Umi => "UMI",
-                BarcodeType::Sample => "SB",
-                BarcodeType::Cell => "CB",
+        match self {
+            BarcodeType::Umi => write!(f, "UMI"),
+            BarcodeType::Sample => write!(f, "SB"),
+            BarcodeType::Cell => write!(f, "CB"),
+            BarcodeType::CellRound(round) => write!(f, "CB{}", round),
+            BarcodeType::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Score of the chain of adapter occurrences: total number of errors and total length
+/// shift of occurrences caused by indels. Lower scores are better.
+type ChainScore = (usize, usize);
+
+/// Regex part of the pattern located before, between or after adapters
+#[derive(Clone)]
+struct RegexSegment {
+    /// Compiled regex pattern
+    regex: Regex,
+
+    /// Min length of the matched sequence
+    min_len: usize,
+
+    /// Max length of the matched sequence, if it is bounded
+    max_len: Option<usize>,
+
+    /// Max lengths of the named capture groups, if they are bounded
+    group_max_lens: Vec<(String, usize)>,
+}
+
+impl RegexSegment {
+    /// Compiles the regex pattern wrapped into `prefix` and `suffix` (e.g. anchors)
+    fn new(pattern: &str, prefix: &str, suffix: &str) -> Result<Self, Error> {
+        let hir = regex_syntax::parse(pattern).ok();
+        let properties = hir.as_ref().map(|hir| hir.properties());
+        let mut group_max_lens = Vec::new();
+        if let Some(hir) = &hir {
+            Self::find_group_max_lens(hir, &mut group_max_lens);
+        }
+        Ok(Self {
+            regex: Regex::new(&format!("{}(?:{}){}", prefix, pattern, suffix))?,
+            min_len: properties
+                .and_then(|properties| properties.minimum_len())
+                .unwrap_or(0),
+            max_len: properties.and_then(|properties| properties.maximum_len()),
+            group_max_lens,
+        })
+    }
+
+    /// Collects max lengths of the named capture groups of the regex
+    fn find_group_max_lens(hir: &Hir, group_max_lens: &mut Vec<(String, usize)>) {
+        match hir.kind() {
+            HirKind::Capture(capture) => {
+                if let (Some(name), Some(max_len)) =
+                    (&capture.name, capture.sub.properties().maximum_len())
+                {
+                    group_max_lens.push((name.to_string(), max_len));
+                }
+                Self::find_group_max_lens(&capture.sub, group_max_lens);
             }
-        )
+            HirKind::Repetition(repetition) => {
+                Self::find_group_max_lens(&repetition.sub, group_max_lens)
+            }
+            HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+                for hir in hirs {
+                    Self::find_group_max_lens(hir, group_max_lens);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks if the length of a sequence fits the regex pattern
+    fn fits(&self, len: usize) -> bool {
+        len >= self.min_len && self.max_len.map_or(true, |max_len| len <= max_len)
+    }
+}
+
+/// Positions of the barcode pattern match in a read sequence
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BarcodeCaptures {
+    /// Positions of the whole pattern match
+    full_match: Range<usize>,
+
+    /// Positions of named capture groups
+    groups: Vec<(String, Range<usize>)>,
+
+    /// Total number of errors in matched adapters
+    errors: usize,
+}
+
+impl BarcodeCaptures {
+    /// Returns positions of the whole pattern match
+    pub fn get_match(&self) -> Range<usize> {
+        self.full_match.clone()
+    }
+
+    /// Returns positions of the named capture group
+    pub fn name(&self, name: &str) -> Option<Range<usize>> {
+        self.groups
+            .iter()
+            .find(|(group_name, _)| group_name == name)
+            .map(|(_, range)| range.clone())
+    }
+
+    /// Returns total number of errors in matched adapters
+    pub fn get_errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Shifts all positions by the `offset`, e.g. from the search window to the whole read
+    fn shift(mut self, offset: usize) -> Self {
+        self.full_match = offset + self.full_match.start..offset + self.full_match.end;
+        for (_, range) in self.groups.iter_mut() {
+            *range = offset + range.start..offset + range.end;
+        }
+        self
+    }
+
+    /// Adds named capture groups found by the regex with the `offset` in a read sequence
+    fn add_groups(&mut self, regex: &Regex, captures: &Captures, offset: usize) {
+        for (idx, name) in regex.capture_names().enumerate() {
+            if let (Some(name), Some(group)) = (name, captures.get(idx)) {
+                self.groups.push((
+                    name.to_owned(),
+                    offset + group.start()..offset + group.end(),
+                ));
+            }
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct BarcodeRegex {
-    /// Regex pattern to parse barcode(s) from read sequence
-    regex: Regex,
+    /// Regex pattern before the first adapter (or the whole pattern, if it has no adapters)
+    regex: Option<RegexSegment>,
+
+    /// Approximately matched adapters with regex patterns following them
+    adapters: Vec<(AdapterMatcher, Option<RegexSegment>)>,
 
     /// List of barcode types parsed from provided pattern
     barcode_types: Vec<BarcodeType>,
+
+    /// Part of the read, where the pattern is searched (the whole read, if `None`)
+    window: Option<SearchWindow>,
 }
 
 impl BarcodeRegex {
@@ -172,73 +756,323 @@ impl BarcodeRegex {
     ///
     /// Example
     /// ```
-    /// use barkit_extract::pattern::BarcodeRegex;
+    /// use barkit_extract::pattern::{BarcodeRegex, MaxError};
     ///
-    /// let barcode_regex = BarcodeRegex::new("^atgc(?<UMI>[ATGCN]{6})", 1);
+    /// let barcode_regex = BarcodeRegex::new("^atgc(?<UMI>[ATGCN]{6})", MaxError::Count(1), false, None, None);
     /// ```
-    pub fn new(pattern: &str, max_error: usize) -> Result<Self, Error> {
-        let barcode_pattern = BarcodePattern::new(pattern, &max_error)?;
-        let fuzzy_pattern = barcode_pattern.get_pattern_with_errors()?;
-        let regex = Regex::new(&fuzzy_pattern)?;
-        let barcode_types = Self::parse_capture_groups(&regex)?;
+    pub fn new(
+        pattern: &str,
+        max_error: MaxError,
+        indels: bool,
+        min_quality: Option<u8>,
+        window: Option<SearchWindow>,
+    ) -> Result<Self, Error> {
+        let barcode_pattern = BarcodePattern::new(pattern, &max_error, indels)?;
+        let segments = barcode_pattern.get_segments()?;
+
+        // Read qualities are used only by adapters matched on their own,
+        // adapters expanded into the regex pattern would ignore them
+        let standalone_adapters = segments
+            .iter()
+            .filter(|segment| matches!(segment, PatternSegment::Adapter { .. }))
+            .count();
+        if min_quality.is_some() && standalone_adapters < barcode_pattern.count_adapters()? {
+            return Err(Error::MinQualityWithExpandedAdapters(pattern.to_owned()));
+        }
+
+        let mut regex = None;
+        let mut adapters: Vec<(AdapterMatcher, Option<RegexSegment>)> = Vec::new();
+        let has_adapters = standalone_adapters > 0;
+
+        for (idx, segment) in segments.iter().enumerate() {
+            match segment {
+                PatternSegment::Adapter {
+                    sequence,
+                    max_error,
+                } => adapters.push((
+                    AdapterMatcher::new(sequence, *max_error, indels, min_quality)?,
+                    None,
+                )),
+                PatternSegment::Regex(pattern) => {
+                    let (prefix, suffix) = match (has_adapters, idx, adapters.is_empty()) {
+                        (false, _, _) => ("", ""),
+                        (true, _, true) => ("", r"\z"),
+                        (true, idx, false) if idx + 1 == segments.len() => (r"\A", ""),
+                        (true, _, false) => (r"\A", r"\z"),
+                    };
+                    let regex_segment = RegexSegment::new(pattern, prefix, suffix)?;
+                    match adapters.last_mut() {
+                        Some((_, following)) => *following = Some(regex_segment),
+                        None => regex = Some(regex_segment),
+                    }
+                }
+            }
+        }
+
+        let barcode_types = Self::parse_capture_groups(
+            regex
+                .iter()
+                .chain(
+                    adapters
+                        .iter()
+                        .filter_map(|(_, following)| following.as_ref()),
+                )
+                .map(|regex_segment| &regex_segment.regex),
+            pattern,
+        )?;
+
         Ok(Self {
             regex,
+            adapters,
             barcode_types,
+            window,
         })
     }
 
-    /// Parses capture groups from regex pattern
-    fn parse_capture_groups(regex: &Regex) -> Result<Vec<BarcodeType>, Error> {
+    /// Parses capture groups from regex patterns
+    fn parse_capture_groups<'a>(
+        regexes: impl Iterator<Item = &'a Regex>,
+        pattern: &str,
+    ) -> Result<Vec<BarcodeType>, Error> {
         let mut capture_groups = Vec::<BarcodeType>::new();
-        for capture_group in regex
-            .capture_names()
-            .collect::<Vec<_>>()
-            .into_iter()
-            .flatten()
-        {
-            capture_groups.push(BarcodeType::parse_type(capture_group)?)
+        for capture_group in regexes.flat_map(|regex| regex.capture_names().flatten()) {
+            let barcode_type = BarcodeType::parse_type(capture_group)?;
+            if capture_groups.contains(&barcode_type) {
+                return Err(Error::DuplicateCaptureGroupName(capture_group.to_owned()));
+            }
+            capture_groups.push(barcode_type)
         }
         if capture_groups.is_empty() {
-            return Err(Error::BarcodeCaptureGroupNotFound(regex.to_string()));
+            return Err(Error::BarcodeCaptureGroupNotFound(pattern.to_owned()));
+        }
+        if capture_groups.contains(&BarcodeType::Cell)
+            && capture_groups
+                .iter()
+                .any(|barcode_type| matches!(barcode_type, BarcodeType::CellRound(_)))
+        {
+            return Err(Error::CellBarcodeWithRounds(pattern.to_owned()));
         }
         Ok(capture_groups)
     }
 
-    /// Captures barcodes in read sequence
+    /// Captures barcodes in read sequence. Read qualities are used to match adapters,
+    /// if the min quality is set. If the search window is set, the pattern is matched
+    /// only to the window and positions of captures are returned in the whole read.
     ///
     /// Example
     /// ```
-    /// use barkit_extract::pattern::BarcodeRegex;
+    /// use barkit_extract::pattern::{BarcodeRegex, MaxError};
     ///
-    /// let barcode_regex = BarcodeRegex::new("^atgc(?<UMI>[ATGCN]{6})", 1).unwrap();
+    /// let barcode_regex = BarcodeRegex::new("^atgc(?<UMI>[ATGCN]{6})", MaxError::Count(1), false, None, None).unwrap();
     ///
-    /// assert_eq!(
-    ///     b"NNNNNN",
-    ///     barcode_regex
-    ///         .get_captures(b"ATGCNNNNNNCCC")
-    ///         .unwrap()
-    ///         .name("UMI")
-    ///         .unwrap()
-    ///         .as_bytes()
-    /// );
+    /// let read_seq = b"ATGCNNNNNNCCC";
+    /// let captures = barcode_regex.get_captures(read_seq, b"IIIIIIIIIIIII").unwrap();
+    ///
+    /// assert_eq!(b"NNNNNN", &read_seq[captures.name("UMI").unwrap()]);
     /// ```
-    pub fn get_captures<'a>(&self, read_seq: &'a [u8]) -> Result<Captures<'a>, Error> {
-        match self.regex.captures(read_seq) {
-            Some(capture) => Ok(capture),
-            None => Err(Error::PatternNotMatched),
+    pub fn get_captures(
+        &self,
+        read_seq: &[u8],
+        read_qual: &[u8],
+    ) -> Result<BarcodeCaptures, Error> {
+        let Some(window) = self.window else {
+            return self.get_window_captures(read_seq, read_qual);
+        };
+        let window = window.resolve(read_seq.len());
+        let window_qual = read_qual.get(window.clone()).unwrap_or_default();
+        self.get_window_captures(&read_seq[window.clone()], window_qual)
+            .map(|captures| captures.shift(window.start))
+    }
+
+    /// Captures barcodes in the part of read sequence
+    fn get_window_captures(
+        &self,
+        read_seq: &[u8],
+        read_qual: &[u8],
+    ) -> Result<BarcodeCaptures, Error> {
+        if self.adapters.is_empty() {
+            let regex = &self.regex.as_ref().ok_or(Error::PatternNotMatched)?.regex;
+            let captures = regex.captures(read_seq).ok_or(Error::PatternNotMatched)?;
+            let full_match = captures.get(0).ok_or(Error::PatternNotMatched)?;
+
+            let mut barcode_captures = BarcodeCaptures {
+                full_match: full_match.start()..full_match.end(),
+                ..Default::default()
+            };
+            barcode_captures.add_groups(regex, &captures, 0);
+            return Ok(barcode_captures);
         }
+
+        self.get_adapter_captures(read_seq, read_qual)
+            .ok_or(Error::PatternNotMatched)
+    }
+
+    /// Captures barcodes in read sequence by the adapter occurrences.
+    ///
+    /// Chooses the chain of adapter occurrences with the least total number of errors,
+    /// which regex patterns between them are matched. So lengths of variable-length
+    /// barcodes are resolved by the best adapter alignment. Among chains with the same
+    /// number of errors, the one with the least indels is chosen, then the leftmost one.
+    fn get_adapter_captures(&self, read_seq: &[u8], read_qual: &[u8]) -> Option<BarcodeCaptures> {
+        let adapter_matches: Vec<Vec<AdapterMatch>> = self
+            .adapters
+            .iter()
+            .map(|(adapter, _)| adapter.find_matches(read_seq, read_qual))
+            .collect();
+
+        // `best_chains[i][j]` is the best score of the chain started from the `j`-th
+        // occurrence of the `i`-th adapter, and the next occurrence in the chain
+        let mut best_chains: Vec<Vec<Option<(ChainScore, usize)>>> =
+            vec![Vec::new(); self.adapters.len()];
+        for idx in (0..self.adapters.len()).rev() {
+            let (adapter, following) = &self.adapters[idx];
+            let following = following.as_ref();
+            best_chains[idx] = adapter_matches[idx]
+                .iter()
+                .map(|adapter_match| {
+                    let score = (
+                        adapter_match.errors,
+                        adapter.get_length_shift(adapter_match),
+                    );
+                    match adapter_matches.get(idx + 1) {
+                        None => following
+                            .map_or(true, |regex_segment| {
+                                regex_segment.regex.is_match(&read_seq[adapter_match.end..])
+                            })
+                            .then_some((score, 0)),
+                        Some(next_matches) => {
+                            let first = next_matches
+                                .partition_point(|next_match| next_match.start < adapter_match.end);
+                            let mut best_chain: Option<(ChainScore, usize)> = None;
+
+                            for (next_idx, next_match) in
+                                next_matches.iter().enumerate().skip(first)
+                            {
+                                let gap = next_match.start - adapter_match.end;
+                                if following.map_or(gap > 0, |regex_segment| {
+                                    regex_segment.max_len.is_some_and(|max_len| gap > max_len)
+                                }) {
+                                    break;
+                                }
+                                let Some((next_score, _)) = best_chains[idx + 1][next_idx] else {
+                                    continue;
+                                };
+                                let chain_score = (score.0 + next_score.0, score.1 + next_score.1);
+                                if best_chain
+                                    .is_some_and(|(best_score, _)| best_score <= chain_score)
+                                {
+                                    continue;
+                                }
+                                if following.map_or(true, |regex_segment| {
+                                    regex_segment.fits(gap)
+                                        && regex_segment.regex.is_match(
+                                            &read_seq[adapter_match.end..next_match.start],
+                                        )
+                                }) {
+                                    best_chain = Some((chain_score, next_idx));
+                                }
+                            }
+                            best_chain
+                        }
+                    }
+                })
+                .collect();
+        }
+
+        let mut best_chain: Option<(ChainScore, usize)> = None;
+        for (idx, adapter_match) in adapter_matches[0].iter().enumerate() {
+            let Some((score, _)) = best_chains[0][idx] else {
+                continue;
+            };
+            if best_chain.is_some_and(|(best_score, _)| best_score <= score) {
+                continue;
+            }
+            if self.regex.as_ref().map_or(true, |regex_segment| {
+                adapter_match.start >= regex_segment.min_len
+                    && regex_segment
+                        .regex
+                        .is_match(&read_seq[..adapter_match.start])
+            }) {
+                best_chain = Some((score, idx));
+            }
+        }
+        let ((errors, _), mut match_idx) = best_chain?;
+
+        let mut barcode_captures = BarcodeCaptures {
+            errors,
+            ..Default::default()
+        };
+
+        let first_match = adapter_matches[0][match_idx];
+        let mut start = first_match.start;
+        if let Some(regex_segment) = &self.regex {
+            let captures = regex_segment
+                .regex
+                .captures(&read_seq[..first_match.start])?;
+            start = captures.get(0)?.start();
+            barcode_captures.add_groups(&regex_segment.regex, &captures, 0);
+        }
+
+        let mut end = first_match.end;
+        for (idx, (_, following)) in self.adapters.iter().enumerate() {
+            let adapter_match = adapter_matches[idx][match_idx];
+            let (_, next_idx) = best_chains[idx][match_idx]?;
+            let next_start = adapter_matches
+                .get(idx + 1)
+                .map_or(read_seq.len(), |next_matches| next_matches[next_idx].start);
+
+            end = adapter_match.end;
+            if let Some(regex_segment) = following {
+                let captures = regex_segment
+                    .regex
+                    .captures(&read_seq[adapter_match.end..next_start])?;
+                end = adapter_match.end + captures.get(0)?.end();
+                barcode_captures.add_groups(&regex_segment.regex, &captures, adapter_match.end);
+            }
+            match_idx = next_idx;
+        }
+
+        barcode_captures.full_match = start..end;
+        Some(barcode_captures)
     }
 
     pub fn get_barcode_types(&self) -> Vec<BarcodeType> {
         self.barcode_types.clone()
     }
+
+    /// Returns max length of the barcode capture group, if it is bounded
+    ///
+    /// Example
+    /// ```
+    /// use barkit_extract::pattern::{BarcodeRegex, MaxError};
+    ///
+    /// let barcode_regex = BarcodeRegex::new("^(?<CB>[ATGCN]{8,11})atgccat(?<UMI>[ATGCN]+)", MaxError::Count(1), false, None, None).unwrap();
+    ///
+    /// assert_eq!(Some(11), barcode_regex.get_max_len("CB"));
+    /// assert_eq!(None, barcode_regex.get_max_len("UMI"));
+    /// ```
+    pub fn get_max_len(&self, name: &str) -> Option<usize> {
+        self.regex
+            .iter()
+            .chain(
+                self.adapters
+                    .iter()
+                    .filter_map(|(_, following)| following.as_ref()),
+            )
+            .flat_map(|regex_segment| &regex_segment.group_max_lens)
+            .find(|(group_name, _)| group_name == name)
+            .map(|(_, max_len)| *max_len)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::ops::Range;
+
     use rstest::rstest;
 
-    use crate::pattern;
+    use crate::error::Error;
+    use crate::pattern::{self, MaxError, PatternSegment};
 
     #[rstest]
     #[case(vec!["."], "a", 1)]
@@ -247,12 +1081,15 @@ mod tests {
     #[case(vec!["AAA.", "AA.A", "A.AA", ".AAA"], "AAAA", 1)]
     #[case(vec!["..."], "AAA", 3)]
     #[case(vec!["..."], "AAA", 4)]
+    #[case(vec!["[AG]A[ACGTN]"], "ran", 0)]
+    #[case(vec!["[AG]A.", "[AG].[ACGTN]", ".A[ACGTN]"], "ran", 1)]
     fn test_generate_sequences_with_pcr_errors(
         #[case] expected: Vec<&str>,
         #[case] text: &str,
         #[case] max_error: usize,
     ) {
-        let barcode_pattern = pattern::BarcodePattern::new("", &max_error).unwrap();
+        let barcode_pattern =
+            pattern::BarcodePattern::new("", &MaxError::Count(max_error), false).unwrap();
         assert_eq!(
             expected,
             barcode_pattern.get_sequence_with_errors(text).unwrap()
@@ -271,7 +1108,248 @@ mod tests {
     )]
     #[case("^(?P<UMI>[ATGCN]{3})", "^(?P<UMI>[ATGCN]{3})", 1)]
     fn test_create_fuzzy(#[case] expected: &str, #[case] pattern: &str, #[case] max_error: usize) {
-        let barcode_pattern = pattern::BarcodePattern::new(pattern, &max_error).unwrap();
+        let barcode_pattern =
+            pattern::BarcodePattern::new(pattern, &MaxError::Count(max_error), false).unwrap();
         assert_eq!(expected, barcode_pattern.get_pattern_with_errors().unwrap())
     }
+
+    #[rstest]
+    #[case(vec!["A"], "a", 0)]
+    #[case(vec!["A", ".", "", ".A", "A."], "a", 1)]
+    #[case(vec!["AA", ".A", "A", ".AA", "A.", "A.A", "AA."], "AA", 1)]
+    fn test_generate_sequences_with_indels(
+        #[case] expected: Vec<&str>,
+        #[case] text: &str,
+        #[case] max_error: usize,
+    ) {
+        let barcode_pattern =
+            pattern::BarcodePattern::new("", &MaxError::Count(max_error), true).unwrap();
+        assert_eq!(
+            expected,
+            barcode_pattern.get_sequence_with_errors(text).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case(b"ATGCAAAACCC", b"AAAA")]
+    #[case(b"ATCAAAACCC", b"AAAA")]
+    #[case(b"ATTGCAAAACCC", b"AAAA")]
+    #[case(b"AAGCAAAACCC", b"AAAA")]
+    fn test_captures_with_indels(#[case] read_seq: &[u8], #[case] umi: &[u8]) {
+        let barcode_regex = pattern::BarcodeRegex::new(
+            "^atgc(?<UMI>[ATGCN]{4})",
+            MaxError::Count(1),
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+        let captures = barcode_regex.get_captures(read_seq, &[]).unwrap();
+        assert_eq!(umi, &read_seq[captures.name("UMI").unwrap()]);
+    }
+
+    #[rstest]
+    #[case(b"TTTAAACCGGTTCC", b"TTTA", b"CC")]
+    #[case(b"TTTAGACCGGTTCC", b"TTTA", b"CC")]
+    #[case(b"TTTAAACCGTTCC", b"TTTA", b"CC")]
+    #[case(b"TTTTTAACCGGTTCC", b"TTTTT", b"CC")]
+    #[case(b"TTTAACCGGTTCC", b"TTT", b"CC")]
+    fn test_captures_with_variable_length(
+        #[case] read_seq: &[u8],
+        #[case] cb: &[u8],
+        #[case] umi: &[u8],
+    ) {
+        let barcode_regex = pattern::BarcodeRegex::new(
+            "^(?<CB>[ATGCN]{3,5})aaccggtt(?<UMI>[ATGCN]{2})",
+            MaxError::Count(1),
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+        let captures = barcode_regex.get_captures(read_seq, &[]).unwrap();
+        assert_eq!(cb, &read_seq[captures.name("CB").unwrap()]);
+        assert_eq!(umi, &read_seq[captures.name("UMI").unwrap()]);
+    }
+
+    #[rstest]
+    #[case(b"ATCCAAAACCC", b"II#IIIIIIII", Some(0))]
+    #[case(b"ATCCAAAACCC", b"IIIIIIIIIII", Some(1))]
+    #[case(b"TTCCAAAACCC", b"II#IIIIIIII", Some(1))]
+    #[case(b"TTCCAAAACCC", b"IIIIIIIIIII", None)]
+    fn test_captures_with_qualities(
+        #[case] read_seq: &[u8],
+        #[case] read_qual: &[u8],
+        #[case] errors: Option<usize>,
+    ) {
+        let barcode_regex = pattern::BarcodeRegex::new(
+            "^atgc(?<UMI>[ATGCN]{4})",
+            MaxError::Count(1),
+            false,
+            Some(20),
+            None,
+        )
+        .unwrap();
+        let captures = barcode_regex.get_captures(read_seq, read_qual).ok();
+        assert_eq!(errors, captures.map(|captures| captures.get_errors()));
+    }
+
+    #[rstest]
+    #[case("^(?<UMI>[ATGCN]{4})(?:atgc)")]
+    #[case("^(?<UMI>[ATGCN]{4})atgc+")]
+    #[case("^(?<UMI>[ATGCN]{4})atgc|^tata")]
+    fn test_min_quality_with_expanded_adapters(#[case] pattern: &str) {
+        assert!(matches!(
+            pattern::BarcodeRegex::new(pattern, MaxError::Count(1), false, Some(20), None),
+            Err(Error::MinQualityWithExpandedAdapters(_))
+        ));
+        assert!(pattern::BarcodeRegex::new(pattern, MaxError::Count(1), false, None, None).is_ok());
+    }
+
+    #[rstest]
+    #[case(vec![PatternSegment::Regex("^(?P<UMI>[ATGCN]{3})".to_string())], "^(?P<UMI>[ATGCN]{3})")]
+    #[case(vec![PatternSegment::Regex("^(?P<cb>[ATGCN]{3})".to_string())], "^(?P<cb>[ATGCN]{3})")]
+    #[case(vec![PatternSegment::Regex("^((AA.|A.A|.AA))(?P<UMI>[ATGCN]{3})".to_string())], "^(aaa)(?P<UMI>[ATGCN]{3})")]
+    #[case(vec![PatternSegment::Regex("(AA.|A.A|.AA)+(?P<UMI>[ATGCN]{3})".to_string())], "aaa+(?P<UMI>[ATGCN]{3})")]
+    #[case(vec![PatternSegment::Regex("(AA.|A.A|.AA)|(?P<UMI>[ATGCN]{3})".to_string())], "aaa|(?P<UMI>[ATGCN]{3})")]
+    #[case(
+        vec![
+            PatternSegment::Adapter { sequence: "AAA".to_string(), max_error: 1 },
+            PatternSegment::Regex("(?P<UMI>[ATGCN]{3})".to_string()),
+            PatternSegment::Adapter { sequence: "CCC".to_string(), max_error: 1 },
+            PatternSegment::Regex("$".to_string()),
+        ],
+        "aaa(?P<UMI>[ATGCN]{3})ccc$"
+    )]
+    #[case(
+        vec![
+            PatternSegment::Adapter { sequence: "AAA".to_string(), max_error: 0 },
+            PatternSegment::Regex("(?P<UMI>[ATGCN]{3})".to_string()),
+            PatternSegment::Adapter { sequence: "CCCCCC".to_string(), max_error: 2 },
+        ],
+        "aaa{e=0}(?P<UMI>[ATGCN]{3})cccccc{e=2}"
+    )]
+    #[case(
+        vec![
+            PatternSegment::Adapter { sequence: "AAAAAAAAAA".to_string(), max_error: 2 },
+            PatternSegment::Regex("(?P<UMI>[ATGCN]{3})".to_string()),
+            PatternSegment::Adapter { sequence: "CCCC".to_string(), max_error: 1 },
+        ],
+        "aaaaaaaaaa{r=0.25}(?P<UMI>[ATGCN]{3})cccc"
+    )]
+    #[case(
+        vec![PatternSegment::Regex("^((AAA))(?P<UMI>[ATGCN]{3})((CC.|C.C|.CC))".to_string())],
+        "^(aaa{e=0})(?P<UMI>[ATGCN]{3})(ccc)"
+    )]
+    fn test_get_segments(#[case] expected: Vec<PatternSegment>, #[case] pattern: &str) {
+        let barcode_pattern =
+            pattern::BarcodePattern::new(pattern, &MaxError::Count(1), false).unwrap();
+        assert_eq!(expected, barcode_pattern.get_segments().unwrap())
+    }
+
+    #[rstest]
+    #[case("(?P<UMI>[ATGCN]{3}){e=1}")]
+    #[case("aaa{e=1}{e=2}(?P<UMI>[ATGCN]{3})")]
+    fn test_orphan_annotation(#[case] pattern: &str) {
+        assert!(matches!(
+            pattern::BarcodePattern::new(pattern, &MaxError::Count(1), false),
+            Err(Error::OrphanAnnotation(_))
+        ))
+    }
+
+    #[rstest]
+    #[case("aaa{e=x}(?P<UMI>[ATGCN]{3})")]
+    #[case("aaa{e=-1}(?P<UMI>[ATGCN]{3})")]
+    #[case("aaa{x=1}(?P<UMI>[ATGCN]{3})")]
+    #[case("aaa{r=1.5}(?P<UMI>[ATGCN]{3})")]
+    #[case("aaa{e=0.1}(?P<UMI>[ATGCN]{3})")]
+    fn test_invalid_annotation(#[case] pattern: &str) {
+        assert!(matches!(
+            pattern::BarcodePattern::new(pattern, &MaxError::Count(1), false),
+            Err(Error::InvalidAnnotation(_))
+        ))
+    }
+
+    #[rstest]
+    #[case(b"ATGCAAAACCC", Some((0, 8, b"AAAA".as_slice())), "^atgc(?<UMI>[ATGCN]{4})")]
+    #[case(b"ATCCAAAACCC", Some((0, 8, b"AAAA".as_slice())), "^atgc(?<UMI>[ATGCN]{4})")]
+    #[case(b"ATCCAAAACCC", None, "^atgc(?<UMI>[ATGCN]{4})$")]
+    #[case(b"TTTAAAACAGGTT", Some((3, 13, b"AAAA".as_slice())), "(?<UMI>[ATGCN]{4})caggtt")]
+    #[case(b"TTAAAAGGACCCCGG", Some((2, 13, b"CC".as_slice())), "aaaa(?:GG)acc(?<UMI>C+)")]
+    #[case(b"GGTATAGGTATACCTT", Some((8, 16, b"CCTT".as_slice())), "tata(?<UMI>[ATGCN]{4})$")]
+    #[case(b"GGTATAGGCCCC", None, "tata(?<UMI>[ATGCN]{4})gg")]
+    #[case(b"ATCCAAAACCC", None, "^atgc{e=0}(?<UMI>[ATGCN]{4})")]
+    #[case(b"ATCCAAAACCC", Some((0, 8, b"AAAA".as_slice())), "^atgc(?<UMI>[ATGCN]{4})(?<cb>)")]
+    #[case(b"TTCCAAAACCC", Some((0, 8, b"AAAA".as_slice())), "^atgc{e=2}(?<UMI>[ATGCN]{4})")]
+    fn test_get_captures(
+        #[case] read_seq: &[u8],
+        #[case] expected: Option<(usize, usize, &[u8])>,
+        #[case] pattern: &str,
+    ) {
+        let barcode_regex =
+            pattern::BarcodeRegex::new(pattern, MaxError::Count(1), false, None, None).unwrap();
+        let captures = barcode_regex
+            .get_captures(read_seq, &[])
+            .ok()
+            .map(|captures| {
+                let full_match = captures.get_match();
+                let umi = &read_seq[captures.name("UMI").unwrap()];
+                (full_match.start, full_match.end, umi)
+            });
+        assert_eq!(expected, captures);
+    }
+
+    #[rstest]
+    #[case("0..40", 0..40)]
+    #[case("-30..", 120..150)]
+    #[case("..-100", 0..50)]
+    #[case("140..200", 140..150)]
+    #[case("100..50", 100..100)]
+    #[case("..", 0..150)]
+    fn test_search_window(#[case] window: &str, #[case] expected: Range<usize>) {
+        let window: pattern::SearchWindow = window.parse().unwrap();
+        assert_eq!(expected, window.resolve(150));
+    }
+
+    #[rstest]
+    #[case("0:40")]
+    #[case("a..40")]
+    #[case("0..4.5")]
+    fn test_invalid_search_window(#[case] window: &str) {
+        assert!(matches!(
+            window.parse::<pattern::SearchWindow>(),
+            Err(Error::InvalidSearchWindow(_))
+        ))
+    }
+
+    #[rstest]
+    #[case(b"ATGCAAAACCCTATACCTT", "0..10", Some((0, 8, b"AAAA".as_slice())), "atgc(?<UMI>[ATGCN]{4})")]
+    #[case(b"CCATGCAAAACCCTATACCTT", "..10", None, "^atgc(?<UMI>[ATGCN]{4})")]
+    #[case(b"ATGCAAAACCCTATACCTT", "-8..", Some((11, 19, b"CCTT".as_slice())), "^tata(?<UMI>[ATGCN]{4})")]
+    #[case(b"ATGCAAAACCCTATACCTT", "-8..", None, "atgc(?<UMI>[ATGCN]{4})")]
+    #[case(b"GGGGGTTTAAAACAGGTT", "5..", Some((8, 18, b"AAAA".as_slice())), "(?<UMI>[ATGCN]{4})caggtt")]
+    fn test_get_captures_in_window(
+        #[case] read_seq: &[u8],
+        #[case] window: &str,
+        #[case] expected: Option<(usize, usize, &[u8])>,
+        #[case] pattern: &str,
+    ) {
+        let barcode_regex = pattern::BarcodeRegex::new(
+            pattern,
+            MaxError::Count(1),
+            false,
+            None,
+            Some(window.parse().unwrap()),
+        )
+        .unwrap();
+        let captures = barcode_regex
+            .get_captures(read_seq, &[])
+            .ok()
+            .map(|captures| {
+                let full_match = captures.get_match();
+                let umi = &read_seq[captures.name("UMI").unwrap()];
+                (full_match.start, full_match.end, umi)
+            });
+        assert_eq!(expected, captures);
+    }
 }
@@ -0,0 +1,208 @@
+#![allow(clippy::result_large_err)]
+
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::pattern::{BarcodeRegex, MaxError};
+use crate::whitelist::WhitelistFile;
+
+/// Built-in presets of the common single-cell and UMI protocols. They contain only barcode
+/// patterns, since whitelists depend on the kit version and are provided with `--whitelist`.
+const BUILTIN_PRESETS: &[(&str, &[&str], &[&str])] = &[
+    // 16 nt cell barcode and 10 nt UMI at the start of the forward read
+    (
+        "10x-3p-v2",
+        &["^(?<CB>[ATGCN]{16})(?<UMI>[ATGCN]{10})"],
+        &[],
+    ),
+    // 16 nt cell barcode and 12 nt UMI at the start of the forward read
+    (
+        "10x-3p-v3",
+        &["^(?<CB>[ATGCN]{16})(?<UMI>[ATGCN]{12})"],
+        &[],
+    ),
+    // 16 nt cell barcode and 10 nt UMI filling the 26 nt forward read, so the template
+    // switch oligo following them is not sequenced
+    ("10x-5p", &["^(?<CB>[ATGCN]{16})(?<UMI>[ATGCN]{10})"], &[]),
+    // 12 nt cell barcode and 8 nt UMI at the start of the forward read
+    ("drop-seq", &["^(?<CB>[ATGCN]{12})(?<UMI>[ATGCN]{8})"], &[]),
+    // Two parts of the cell barcode separated by the W1 adapter and 6 nt UMI
+    (
+        "indrop",
+        &["^(?<CB1>[ATGCN]{8,11})gagtgattgcttgtgacgcctt(?<CB2>[ATGCN]{8})(?<UMI>[ATGCN]{6})"],
+        &[],
+    ),
+    // 6 nt UMI and 6 nt cell barcode at the start of the forward read
+    ("cel-seq2", &["^(?<UMI>[ATGCN]{6})(?<CB>[ATGCN]{6})"], &[]),
+    // 12 nt UMI followed by the common sequence at the start of the reverse read
+    ("qiaseq-dna", &[], &["^(?<UMI>[ATGCN]{12})attggagtcct"]),
+];
+
+/// Predefined barcode patterns and parameters of the sequencing protocol
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    /// Name of the preset
+    pub name: String,
+
+    /// Alternative barcode patterns of forward reads
+    #[serde(default)]
+    pub pattern1: Vec<String>,
+
+    /// Alternative barcode patterns of reverse reads
+    #[serde(default)]
+    pub pattern2: Vec<String>,
+
+    /// If `true`, captured patterns will not be trimmed
+    #[serde(default)]
+    pub skip_trimming: bool,
+
+    /// Whitelist of cell barcodes
+    #[serde(default)]
+    pub whitelist: Option<WhitelistFile>,
+
+    /// Whitelists of split-pool round cell barcodes in order of rounds
+    #[serde(default)]
+    pub round_whitelists: Vec<WhitelistFile>,
+}
+
+/// TOML file with user-defined presets
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetsFile {
+    #[serde(default)]
+    preset: Vec<Preset>,
+}
+
+impl Preset {
+    /// Returns built-in presets
+    pub fn builtin() -> Vec<Preset> {
+        BUILTIN_PRESETS
+            .iter()
+            .map(|(name, pattern1, pattern2)| Preset {
+                name: name.to_string(),
+                pattern1: pattern1.iter().map(|pattern| pattern.to_string()).collect(),
+                pattern2: pattern2.iter().map(|pattern| pattern.to_string()).collect(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Parses user-defined presets from the TOML content
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::preset::Preset;
+    ///
+    /// let presets = Preset::parse_presets(r#"
+    ///     [[preset]]
+    ///     name = "my-protocol"
+    ///     pattern1 = ["^(?<UMI>[ATGCN]{8})atgc"]
+    ///     skip_trimming = true
+    /// "#).unwrap();
+    ///
+    /// assert_eq!("my-protocol", presets[0].name);
+    /// ```
+    pub fn parse_presets(content: &str) -> Result<Vec<Preset>, Error> {
+        let presets_file: PresetsFile =
+            toml::from_str(content).map_err(|e| Error::InvalidPresetsFile(e.to_string()))?;
+        Ok(presets_file.preset)
+    }
+
+    /// Finds the preset by its name among user-defined presets from the TOML file and built-in presets.
+    /// User-defined presets take precedence over built-in ones with the same name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::preset::Preset;
+    ///
+    /// let preset = Preset::find("10x-3p-v3", None).unwrap();
+    ///
+    /// assert_eq!(vec!["^(?<CB>[ATGCN]{16})(?<UMI>[ATGCN]{12})"], preset.pattern1);
+    /// ```
+    pub fn find(name: &str, presets_file: Option<&str>) -> Result<Preset, Error> {
+        let mut presets = match presets_file {
+            Some(path) => Self::parse_presets(&fs::read_to_string(path)?)?,
+            None => Vec::new(),
+        };
+        presets.extend(Self::builtin());
+
+        let preset = presets
+            .into_iter()
+            .find(|preset| preset.name == name)
+            .ok_or(Error::UnknownPreset(name.to_owned()))?;
+        preset.validate()?;
+
+        Ok(preset)
+    }
+
+    /// Checks that the preset has valid barcode patterns
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.pattern1.is_empty() && self.pattern2.is_empty() {
+            return Err(Error::EmptyPreset(self.name.clone()));
+        }
+        for pattern in self.pattern1.iter().chain(&self.pattern2) {
+            BarcodeRegex::new(pattern, MaxError::Count(1), false, None, None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::error::Error;
+    use crate::preset::Preset;
+    use crate::whitelist::WhitelistFile;
+
+    #[test]
+    fn test_builtin_presets() {
+        for preset in Preset::builtin() {
+            assert!(
+                preset.validate().is_ok(),
+                "{} preset is invalid",
+                preset.name
+            );
+        }
+    }
+
+    #[rstest]
+    #[case(
+        "[[preset]]\nname = \"a\"\npattern2 = [\"^(?<UMI>[ATGCN]{8})\"]\nwhitelist = \"wl.txt\""
+    )]
+    fn test_parse_presets(#[case] content: &str) {
+        let presets = Preset::parse_presets(content).unwrap();
+        assert_eq!(
+            vec![Preset {
+                name: "a".to_string(),
+                pattern2: vec!["^(?<UMI>[ATGCN]{8})".to_string()],
+                whitelist: Some(WhitelistFile::from("wl.txt".to_string())),
+                ..Default::default()
+            }],
+            presets
+        );
+    }
+
+    #[rstest]
+    #[case("[[preset]]\nname = \"a\"\npatterns = []")]
+    #[case("[[preset]]\npattern1 = []")]
+    fn test_parse_invalid_presets(#[case] content: &str) {
+        assert!(matches!(
+            Preset::parse_presets(content),
+            Err(Error::InvalidPresetsFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_unknown_preset() {
+        assert!(matches!(
+            Preset::find("unknown", None),
+            Err(Error::UnknownPreset(_))
+        ));
+    }
+}
@@ -1,95 +1,491 @@
+use std::sync::Arc;
+
+use rayon::iter::Either;
 use rayon::prelude::*;
-use seq_io::fastq::{OwnedRecord, RefRecord};
+use seq_io::fastq::{OwnedRecord, Record, RefRecord};
 
-use crate::fastq::{CompressionType, FastqReader, FastqWriter, FastqsReader, FastqsWriter};
+use crate::bam;
+use crate::error::Error;
+use crate::fastq::{self, CompressionType, FastqReader, FastqWriter, FastqsReader, FastqsWriter};
+use crate::header::HeaderFormat;
 use crate::logger;
-use crate::parse::{self, BarcodeParser};
-use crate::pattern::BarcodeRegex;
+use crate::parse::{
+    self, Barcode, BarcodeCombiner, BarcodeParser, IndexParser, MatchPolicy, ParsedRead,
+    PatternSelection,
+};
+use crate::pattern::{BarcodeRegex, BarcodeType, MaxError, SearchWindow};
+use crate::whitelist::{Whitelist, WhitelistFile};
+
+/// Source of barcodes in the index FASTQ (I1/I2)
+pub enum IndexBarcodes {
+    /// Alternative barcode patterns of index reads
+    Patterns {
+        patterns: Vec<String>,
+        max_error: MaxError,
+    },
+
+    /// Tag to copy the whole index read sequence into
+    Tag(String),
+}
+
+/// Reason of dropping the pair
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DropReason {
+    /// Required pattern of forward reads is not matched
+    ForwardPattern,
+
+    /// Required pattern of reverse reads is not matched
+    ReversePattern,
+
+    /// Required patterns of both mates are not matched
+    BothPatterns,
+
+    /// Pattern of index reads is not matched
+    IndexPattern,
+}
+
+impl DropReason {
+    /// All reasons in order of the run summary
+    const ALL: [DropReason; 4] = [
+        DropReason::ForwardPattern,
+        DropReason::ReversePattern,
+        DropReason::BothPatterns,
+        DropReason::IndexPattern,
+    ];
+
+    /// Returns the reason by mates with not matched required patterns
+    fn from_failed_mates(failed_mates: [bool; 2]) -> Self {
+        match failed_mates {
+            [true, true] => DropReason::BothPatterns,
+            [false, true] => DropReason::ReversePattern,
+            _ => DropReason::ForwardPattern,
+        }
+    }
+
+    /// Returns description of the reason in the run summary
+    fn description(&self) -> &'static str {
+        match self {
+            DropReason::ForwardPattern => "forward pattern not matched",
+            DropReason::ReversePattern => "reverse pattern not matched",
+            DropReason::BothPatterns => "both patterns not matched",
+            DropReason::IndexPattern => "index pattern not matched",
+        }
+    }
+}
+
+/// Returns summary of dropped pairs by reasons
+fn drop_summary(dropped: &[usize; DropReason::ALL.len()]) -> String {
+    let total: usize = dropped.iter().sum();
+    let reasons: Vec<String> = DropReason::ALL
+        .iter()
+        .zip(dropped)
+        .filter(|(_, count)| **count > 0)
+        .map(|(reason, count)| format!("{}: {}", reason.description(), count))
+        .collect();
+    if reasons.is_empty() {
+        format!("Dropped pairs: {}", total)
+    } else {
+        format!("Dropped pairs: {} ({})", total, reasons.join(", "))
+    }
+}
+
+/// Index FASTQ (I1/I2) read in lockstep with the input FASTQs
+pub struct IndexFastq {
+    /// Path to the index FASTQ
+    pub path: String,
+
+    /// How to get barcodes from index reads
+    pub barcodes: IndexBarcodes,
+}
 
 #[allow(clippy::too_many_arguments)]
 pub fn run(
     fq1: String,
     fq2: Option<String>,
-    pattern1: Option<String>,
-    pattern2: Option<String>,
+    pattern1: Vec<String>,
+    pattern2: Vec<String>,
+    index_fastqs: Vec<IndexFastq>,
     out_fq1: String,
     out_fq2: Option<String>,
+    unmatched_out1: Option<String>,
+    unmatched_out2: Option<String>,
     max_memory: Option<usize>,
     threads: usize,
+    pattern_selection: PatternSelection,
     rc_barcodes: bool,
     skip_trimming: bool,
-    max_error: usize,
+    whitelist: Option<WhitelistFile>,
+    round_whitelists: Vec<WhitelistFile>,
+    pad_barcodes: bool,
+    reorient: bool,
+    barcode_combiner: Option<BarcodeCombiner>,
+    match_policy: MatchPolicy,
+    max_error1: MaxError,
+    max_error2: MaxError,
+    window1: Vec<SearchWindow>,
+    window2: Vec<SearchWindow>,
+    indels: bool,
+    min_quality: Option<u8>,
     output_compression: CompressionType,
+    bam: bool,
+    header_format: HeaderFormat,
     quiet: bool,
     force: bool,
 ) {
     match (fq2, out_fq2, pattern1, pattern2) {
-        (Some(fq2), Some(out_fq2), pattern1, pattern2) => process_pair_end_fastq(
-            fq1,
-            fq2,
-            pattern1,
-            pattern2,
-            out_fq1,
-            out_fq2,
-            max_memory,
-            threads,
-            rc_barcodes,
-            skip_trimming,
-            max_error,
-            output_compression,
-            quiet,
-            force
-        ),
-        (None, None, Some(pattern1), None) => process_single_end_fastq(
-            fq1,
-            pattern1,
-            out_fq1,
-            max_memory,
-            threads,
-            rc_barcodes,
-            skip_trimming,
-            max_error,
-            output_compression,
-            quiet,
-            force
-        ),
+        (Some(fq2), out_fq2, pattern1, pattern2) if out_fq2.is_some() || bam => {
+            process_pair_end_fastq(
+                fq1,
+                fq2,
+                pattern1,
+                pattern2,
+                index_fastqs,
+                out_fq1,
+                out_fq2,
+                unmatched_out1,
+                unmatched_out2,
+                max_memory,
+                threads,
+                pattern_selection,
+                rc_barcodes,
+                skip_trimming,
+                whitelist,
+                round_whitelists,
+                pad_barcodes,
+                reorient,
+                barcode_combiner,
+                match_policy,
+                max_error1,
+                max_error2,
+                window1,
+                window2,
+                indels,
+                min_quality,
+                output_compression,
+                bam,
+                header_format,
+                quiet,
+                force,
+            )
+        }
+        (None, None, pattern1, pattern2) if !pattern1.is_empty() && pattern2.is_empty() => {
+            process_single_end_fastq(
+                fq1,
+                pattern1,
+                index_fastqs,
+                out_fq1,
+                unmatched_out1,
+                max_memory,
+                threads,
+                pattern_selection,
+                rc_barcodes,
+                skip_trimming,
+                whitelist,
+                round_whitelists,
+                pad_barcodes,
+                reorient,
+                max_error1,
+                window1,
+                indels,
+                min_quality,
+                output_compression,
+                bam,
+                header_format,
+                quiet,
+                force,
+            )
+        }
         _ => eprintln!(
-            "Invalid arguments provided. Please ensure that you have provided the correct combination of input files and patterns."
+            "Invalid arguments provided. Please ensure that you have provided the correct \
+             combination of input files and patterns."
         ),
     }
 }
 
-/// Parses barcodes from single-end reads in parallel
+/// Compiles alternative barcode patterns of a mate. A single search window is shared
+/// by all patterns, otherwise each pattern has its own window.
+fn compile_patterns(
+    patterns: &[String],
+    max_error: MaxError,
+    windows: &[SearchWindow],
+    indels: bool,
+    min_quality: Option<u8>,
+) -> Vec<BarcodeRegex> {
+    if windows.len() > 1 && windows.len() != patterns.len() {
+        eprintln!(
+            "{}",
+            Error::SearchWindowsNumber(windows.len(), patterns.len())
+        );
+        std::process::exit(1);
+    }
+    patterns
+        .iter()
+        .enumerate()
+        .map(|(idx, pattern)| {
+            let window = windows.get(idx).or(windows.first()).copied();
+            BarcodeRegex::new(pattern, max_error, indels, min_quality, window).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        })
+        .collect()
+}
+
+/// Reads whitelist of cell barcodes, if it is provided
+fn load_whitelist(whitelist: Option<WhitelistFile>) -> Option<Arc<Whitelist>> {
+    whitelist.map(|whitelist_file| {
+        Arc::new(Whitelist::from_file(&whitelist_file).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }))
+    })
+}
+
+/// Reads whitelists of split-pool round cell barcodes
+fn load_round_whitelists(round_whitelists: Vec<WhitelistFile>) -> Vec<Arc<Whitelist>> {
+    round_whitelists
+        .into_iter()
+        .map(|whitelist_file| {
+            Arc::new(Whitelist::from_file(&whitelist_file).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }))
+        })
+        .collect()
+}
+
+/// Checks that every round cell barcode of patterns has its round whitelist,
+/// if round whitelists are provided
+fn check_rounds(barcode_regexes: &[BarcodeRegex], round_whitelists: &[Arc<Whitelist>]) {
+    if round_whitelists.is_empty() {
+        return;
+    }
+    let max_round = barcode_regexes
+        .iter()
+        .flat_map(|barcode_regex| barcode_regex.get_barcode_types())
+        .filter_map(|barcode_type| match barcode_type {
+            BarcodeType::CellRound(round) => Some(round),
+            _ => None,
+        })
+        .max();
+    if let Some(max_round) = max_round.filter(|max_round| *max_round > round_whitelists.len()) {
+        eprintln!(
+            "{}",
+            Error::RoundWhitelistsNumber(max_round, round_whitelists.len())
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Prints summary of the cell barcodes correction against whitelists
+fn log_whitelists_summary(
+    logger: &logger::Logger,
+    whitelist: &Option<Arc<Whitelist>>,
+    round_whitelists: &[Arc<Whitelist>],
+) {
+    if let Some(whitelist) = whitelist {
+        logger.summary(&whitelist.summary("Cell barcodes"));
+    }
+    for (idx, round_whitelist) in round_whitelists.iter().enumerate() {
+        logger.summary(&round_whitelist.summary(&format!("Round {} barcodes", idx + 1)));
+    }
+}
+
+/// Checks that names of all barcodes can be written as SAM tags, before the output is created
+fn check_sam_tags(
+    barcode_parsers: &[&BarcodeParser],
+    index_parsers: &[IndexParser],
+    barcode_combiner: &Option<BarcodeCombiner>,
+) {
+    let names = barcode_parsers
+        .iter()
+        .flat_map(|barcode_parser| barcode_parser.get_barcode_names())
+        .chain(
+            index_parsers
+                .iter()
+                .flat_map(IndexParser::get_barcode_names),
+        )
+        .chain(
+            barcode_combiner
+                .iter()
+                .flat_map(|combiner| combiner.get_names().to_vec()),
+        );
+
+    for name in names {
+        bam::get_tags(&name).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+}
+
+/// Opens index FASTQs and creates parsers of their barcodes
+#[allow(clippy::too_many_arguments)]
+fn open_index_fastqs(
+    index_fastqs: &[IndexFastq],
+    threads: usize,
+    max_memory: Option<usize>,
+    pattern_selection: PatternSelection,
+    rc_barcodes: bool,
+    whitelist: &Option<Arc<Whitelist>>,
+    round_whitelists: &[Arc<Whitelist>],
+    pad_barcodes: bool,
+    indels: bool,
+    min_quality: Option<u8>,
+) -> (Vec<FastqReader>, Vec<IndexParser>) {
+    index_fastqs
+        .iter()
+        .map(|index_fastq| {
+            let reader =
+                FastqReader::new(&index_fastq.path, threads, max_memory).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            let index_parser = match &index_fastq.barcodes {
+                IndexBarcodes::Patterns {
+                    patterns,
+                    max_error,
+                } => {
+                    let barcodes = compile_patterns(patterns, *max_error, &[], indels, min_quality);
+                    check_rounds(&barcodes, round_whitelists);
+                    IndexParser::Patterns(
+                        BarcodeParser::new(
+                            &barcodes,
+                            pattern_selection,
+                            true,
+                            rc_barcodes,
+                            whitelist.clone(),
+                            round_whitelists.to_vec(),
+                            pad_barcodes,
+                            false,
+                        )
+                        .expect("BarcodeParser is created with the index barcode pattern"),
+                    )
+                }
+                IndexBarcodes::Tag(tag) => IndexParser::Tag(tag.clone()),
+            };
+            (reader, index_parser)
+        })
+        .unzip()
+}
+
+/// Reads the same number of records from index FASTQs as in the record set of the input FASTQ
+/// and checks that they have the same read names
+fn read_index_records(
+    index_readers: &mut [FastqReader],
+    index_fastqs: &[IndexFastq],
+    records: &[RefRecord],
+) -> Vec<Vec<OwnedRecord>> {
+    index_readers
+        .iter_mut()
+        .zip(index_fastqs)
+        .map(|(index_reader, index_fastq)| {
+            let index_records = index_reader
+                .read_records(records.len())
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            if index_records.len() < records.len() {
+                eprintln!("{}", Error::IndexReadsNumber(index_fastq.path.clone()));
+                std::process::exit(1);
+            }
+            fastq::check_read_names(
+                &index_fastq.path,
+                records.iter().map(|record| record.head()),
+                &index_records,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            index_records
+        })
+        .collect()
+}
+
+/// Checks that index FASTQs do not contain more reads than the input FASTQ
+fn check_index_readers_exhausted(index_readers: &mut [FastqReader], index_fastqs: &[IndexFastq]) {
+    for (index_reader, index_fastq) in index_readers.iter_mut().zip(index_fastqs) {
+        let is_exhausted = index_reader.is_exhausted().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        if !is_exhausted {
+            eprintln!("{}", Error::IndexReadsNumber(index_fastq.path.clone()));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns barcodes of index reads at the specified position of the record set.
+/// Returns `None`, if barcodes are not found in any of index reads.
+fn get_index_barcodes(
+    idx: usize,
+    index_parsers: &[IndexParser],
+    index_records: &[Vec<OwnedRecord>],
+) -> Option<Vec<Barcode>> {
+    let mut barcodes = Vec::new();
+    for (index_parser, records) in index_parsers.iter().zip(index_records) {
+        barcodes.extend(index_parser.parse_barcodes(&records[idx])?);
+    }
+    Some(barcodes)
+}
+
+/// Parses barcodes from single-end reads in parallel.
+/// Returns parsed reads and original records of unmatched reads.
 fn parse_se_reads(
     records: &Vec<RefRecord>,
-    barcode: &BarcodeRegex,
-    skip_trimming: bool,
-    rc_barcodes: bool,
-) -> Vec<OwnedRecord> {
+    barcode_parser: &BarcodeParser,
+    index_parsers: &[IndexParser],
+    index_records: &[Vec<OwnedRecord>],
+) -> (Vec<ParsedRead>, Vec<OwnedRecord>) {
     records
         .par_iter()
-        .filter_map(|record| {
-            // Create a new BarcodeParser with the appropriate configuration
-            let barcodes_parser = BarcodeParser::new(Some(barcode), skip_trimming, rc_barcodes);
-
-            // Parse the barcodes from the RefRecord
-            // `record` needs to be passed as a `&RefRecord`
-            barcodes_parser?.parse_barcodes(record)
+        .enumerate()
+        // Parse the barcodes from the RefRecord
+        // `record` needs to be passed as a `&RefRecord`
+        .partition_map(|(idx, record)| {
+            let new_read = barcode_parser
+                .parse_barcodes(record)
+                .and_then(|mut new_read| {
+                    new_read.barcodes.extend(get_index_barcodes(
+                        idx,
+                        index_parsers,
+                        index_records,
+                    )?);
+                    Some(new_read)
+                });
+            match new_read {
+                Some(new_read) => Either::Left(new_read),
+                None => Either::Right(record.to_owned_record()),
+            }
         })
-        .collect()
 }
 
 #[allow(clippy::too_many_arguments)]
 fn process_single_end_fastq(
     fq: String,
-    pattern: String,
+    patterns: Vec<String>,
+    index_fastqs: Vec<IndexFastq>,
     out_fq: String,
+    unmatched_out: Option<String>,
     max_memory: Option<usize>,
     threads: usize,
+    pattern_selection: PatternSelection,
     rc_barcodes: bool,
     skip_trimming: bool,
-    max_error: usize,
+    whitelist: Option<WhitelistFile>,
+    round_whitelists: Vec<WhitelistFile>,
+    pad_barcodes: bool,
+    reorient: bool,
+    max_error: MaxError,
+    window: Vec<SearchWindow>,
+    indels: bool,
+    min_quality: Option<u8>,
     output_compression: CompressionType,
+    bam: bool,
+    header_format: HeaderFormat,
     quiet: bool,
     force: bool,
 ) {
@@ -104,18 +500,60 @@ fn process_single_end_fastq(
         std::process::exit(1);
     });
 
-    let mut writer =
-        FastqWriter::new(&out_fq, &output_compression, threads, force).unwrap_or_else(|e| {
+    logger.message("Parsing barcode patterns...");
+
+    let barcodes = compile_patterns(&patterns, max_error, &window, indels, min_quality);
+    let whitelist = load_whitelist(whitelist);
+    let round_whitelists = load_round_whitelists(round_whitelists);
+    check_rounds(&barcodes, &round_whitelists);
+
+    // Create a new BarcodeParser with the appropriate configuration
+    let barcode_parser = BarcodeParser::new(
+        &barcodes,
+        pattern_selection,
+        skip_trimming,
+        rc_barcodes,
+        whitelist.clone(),
+        round_whitelists.clone(),
+        pad_barcodes,
+        reorient,
+    )
+    .expect("BarcodeParser is created with the barcode pattern");
+
+    let (mut index_readers, index_parsers) = open_index_fastqs(
+        &index_fastqs,
+        threads,
+        max_memory,
+        pattern_selection,
+        rc_barcodes,
+        &whitelist,
+        &round_whitelists,
+        pad_barcodes,
+        indels,
+        min_quality,
+    );
+
+    if bam || header_format == HeaderFormat::Sam {
+        check_sam_tags(&[&barcode_parser], &index_parsers, &None);
+    }
+
+    let mut unmatched_writer = unmatched_out.map(|unmatched_out| {
+        FastqWriter::new(&unmatched_out, &output_compression, threads, force).unwrap_or_else(|e| {
             eprintln!("{}", e);
             std::process::exit(1);
-        });
-
-    logger.message("Parsing barcode patterns...");
+        })
+    });
 
-    let barcode = BarcodeRegex::new(&pattern, max_error).unwrap_or_else(|e| {
+    let mut writer = if bam {
+        FastqWriter::new_bam(&out_fq, threads, force)
+    } else {
+        FastqWriter::new(&out_fq, &output_compression, threads, force)
+    }
+    .unwrap_or_else(|e| {
         eprintln!("{}", e);
         std::process::exit(1);
     });
+    writer.set_header_format(header_format);
 
     logger.message("Extracting barcodes from reads...");
 
@@ -126,14 +564,25 @@ fn process_single_end_fastq(
             // Flatten the record set into individual records
             let records = records.into_iter().collect::<Vec<_>>();
 
+            let index_records = read_index_records(&mut index_readers, &index_fastqs, &records);
+
             // Parallel processing of individual records to extract parsed reads
-            let result_reads = parse_se_reads(&records, &barcode, skip_trimming, rc_barcodes);
+            let (result_reads, unmatched_reads) =
+                parse_se_reads(&records, &barcode_parser, &index_parsers, &index_records);
 
             // Write the processed reads to the output FASTQ
-            writer.write_all(result_reads).unwrap_or_else(|e| {
+            writer.write_parsed_all(result_reads).unwrap_or_else(|e| {
                 eprintln!("{}", e);
                 std::process::exit(1);
             });
+            if let Some(unmatched_writer) = &mut unmatched_writer {
+                unmatched_writer
+                    .write_all(unmatched_reads)
+                    .unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    });
+            }
 
             // Increment the progress tracker based on the number of records processed
             logger.increment_progress(records.len());
@@ -142,72 +591,125 @@ fn process_single_end_fastq(
         }
     }
 
+    check_index_readers_exhausted(&mut index_readers, &index_fastqs);
+
+    log_whitelists_summary(&logger, &whitelist, &round_whitelists);
+    if rc_barcodes {
+        logger.summary(&barcode_parser.summary("Read orientations"));
+    }
     logger.final_message();
 }
 
-/// Returns final reads, that will be saved to the output file
+/// Returns final reads, that will be saved to the output file.
+/// Mates without matched patterns are saved unchanged.
 fn get_new_reads(
-    new_records: (Option<OwnedRecord>, Option<OwnedRecord>),
+    new_records: (Option<ParsedRead>, Option<ParsedRead>),
     record1: &RefRecord,
-    record2: &RefRecord,
-) -> Option<(OwnedRecord, OwnedRecord)> {
-    match new_records {
-        (Some(new_record1), Some(new_record2)) => Some((new_record1, new_record2)),
-        (None, Some(new_record2)) => Some((record1.to_owned_record(), new_record2)),
-        (Some(new_record1), None) => Some((new_record1, record2.to_owned_record())),
-        (None, None) => None,
-    }
+    record2: &OwnedRecord,
+) -> (ParsedRead, ParsedRead) {
+    let (new_record1, new_record2) = new_records;
+    (
+        new_record1.unwrap_or_else(|| ParsedRead::from_record(record1)),
+        new_record2.unwrap_or_else(|| ParsedRead::from_record(record2)),
+    )
 }
 
-/// Parses barcodes from paired-end reads in parallel
+/// Parses barcodes from paired-end reads in parallel and keeps pairs by the match policy.
+/// Returns parsed mates and original records of dropped mates with reasons.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 fn parse_pe_reads(
     records1: &Vec<RefRecord>,
-    records2: &Vec<RefRecord>,
-    barcode1: &Option<BarcodeRegex>,
-    barcode2: &Option<BarcodeRegex>,
-    skip_trimming: bool,
-    rc_barcodes: bool,
-) -> Vec<(OwnedRecord, OwnedRecord)> {
+    records2: &[OwnedRecord],
+    barcode1_parser: &Option<BarcodeParser>,
+    barcode2_parser: &Option<BarcodeParser>,
+    barcode_combiner: &Option<BarcodeCombiner>,
+    match_policy: &MatchPolicy,
+    index_parsers: &[IndexParser],
+    index_records: &[Vec<OwnedRecord>],
+) -> (
+    Vec<(ParsedRead, ParsedRead)>,
+    Vec<(DropReason, (OwnedRecord, OwnedRecord))>,
+) {
+    let parse_mates = |idx: usize, record1: &RefRecord, record2: &OwnedRecord| {
+        // Parse the barcodes from the RefRecord
+        // `record` needs to be passed as a `&RefRecord`
+        let new_reads = (
+            barcode1_parser
+                .as_ref()
+                .and_then(|parser| parser.parse_barcodes(record1)),
+            barcode2_parser
+                .as_ref()
+                .and_then(|parser| parser.parse_barcodes(record2)),
+        );
+
+        // Matches are `None` for mates without patterns
+        let matches = [
+            barcode1_parser.as_ref().map(|_| new_reads.0.is_some()),
+            barcode2_parser.as_ref().map(|_| new_reads.1.is_some()),
+        ];
+        if let Some(failed_mates) = match_policy.get_failed_mates(matches) {
+            return Err(DropReason::from_failed_mates(failed_mates));
+        }
+
+        let (mut new_read1, mut new_read2) = get_new_reads(new_reads, record1, record2);
+        if let Some(barcode_combiner) = barcode_combiner {
+            barcode_combiner.combine(&mut new_read1, &mut new_read2);
+        }
+
+        // Barcodes of index reads are added to both mates
+        let index_barcodes = get_index_barcodes(idx, index_parsers, index_records)
+            .ok_or(DropReason::IndexPattern)?;
+        new_read1.barcodes.extend(index_barcodes.iter().cloned());
+        new_read2.barcodes.extend(index_barcodes);
+
+        Ok((new_read1, new_read2))
+    };
+
     records1
         .par_iter()
         .zip(records2.par_iter())
-        .filter_map(|(record1, record2)| {
-            // Create a new `BarcodeParser` with the appropriate configuration for forward and reverse reads
-            let barcode1_parser =
-                parse::BarcodeParser::new(barcode1.as_ref(), skip_trimming, rc_barcodes);
-            let barcode2_parser =
-                parse::BarcodeParser::new(barcode2.as_ref(), skip_trimming, rc_barcodes);
-
-            // Parse the barcodes from the RefRecord
-            // `record` needs to be passed as a `&RefRecord`
-            let new_reads = (
-                barcode1_parser
-                    .as_ref()
-                    .and_then(|parser| parser.parse_barcodes(record1)),
-                barcode2_parser
-                    .as_ref()
-                    .and_then(|parser| parser.parse_barcodes(record2)),
-            );
-
-            get_new_reads(new_reads, record1, record2)
-        })
-        .collect()
+        .enumerate()
+        .partition_map(
+            |(idx, (record1, record2))| match parse_mates(idx, record1, record2) {
+                Ok(new_reads) => Either::Left(new_reads),
+                Err(reason) => {
+                    Either::Right((reason, (record1.to_owned_record(), record2.clone())))
+                }
+            },
+        )
 }
 
 #[allow(clippy::too_many_arguments)]
 fn process_pair_end_fastq(
     fq1: String,
     fq2: String,
-    pattern1: Option<String>,
-    pattern2: Option<String>,
+    pattern1: Vec<String>,
+    pattern2: Vec<String>,
+    index_fastqs: Vec<IndexFastq>,
     out_fq1: String,
-    out_fq2: String,
+    out_fq2: Option<String>,
+    unmatched_out1: Option<String>,
+    unmatched_out2: Option<String>,
     max_memory: Option<usize>,
     threads: usize,
+    pattern_selection: PatternSelection,
     rc_barcodes: bool,
     skip_trimming: bool,
-    max_error: usize,
+    whitelist: Option<WhitelistFile>,
+    round_whitelists: Vec<WhitelistFile>,
+    pad_barcodes: bool,
+    reorient: bool,
+    barcode_combiner: Option<BarcodeCombiner>,
+    match_policy: MatchPolicy,
+    max_error1: MaxError,
+    max_error2: MaxError,
+    window1: Vec<SearchWindow>,
+    window2: Vec<SearchWindow>,
+    indels: bool,
+    min_quality: Option<u8>,
     output_compression: CompressionType,
+    bam: bool,
+    header_format: HeaderFormat,
     quiet: bool,
     force: bool,
 ) {
@@ -222,55 +724,154 @@ fn process_pair_end_fastq(
         std::process::exit(1);
     });
 
-    let mut writer = FastqsWriter::new(&out_fq1, &out_fq2, &output_compression, threads, force)
-        .unwrap_or_else(|e| {
-            eprintln!("{}", e);
-            std::process::exit(1);
-        });
-
     logger.message("Parsing barcode patterns...");
 
-    let barcode1 = pattern1.as_ref().map(|pat| {
-        BarcodeRegex::new(pat, max_error).unwrap_or_else(|e| {
-            eprintln!("{}", e);
-            std::process::exit(1);
-        })
-    });
+    let barcodes1 = compile_patterns(&pattern1, max_error1, &window1, indels, min_quality);
+    let barcodes2 = compile_patterns(&pattern2, max_error2, &window2, indels, min_quality);
+    let whitelist = load_whitelist(whitelist);
+    let round_whitelists = load_round_whitelists(round_whitelists);
+    check_rounds(&barcodes1, &round_whitelists);
+    check_rounds(&barcodes2, &round_whitelists);
+
+    // Create a new `BarcodeParser` with the appropriate configuration for forward and reverse reads
+    let barcode1_parser = parse::BarcodeParser::new(
+        &barcodes1,
+        pattern_selection,
+        skip_trimming,
+        rc_barcodes,
+        whitelist.clone(),
+        round_whitelists.clone(),
+        pad_barcodes,
+        reorient,
+    );
+    let barcode2_parser = parse::BarcodeParser::new(
+        &barcodes2,
+        pattern_selection,
+        skip_trimming,
+        rc_barcodes,
+        whitelist.clone(),
+        round_whitelists.clone(),
+        pad_barcodes,
+        reorient,
+    );
+
+    let (mut index_readers, index_parsers) = open_index_fastqs(
+        &index_fastqs,
+        threads,
+        max_memory,
+        pattern_selection,
+        rc_barcodes,
+        &whitelist,
+        &round_whitelists,
+        pad_barcodes,
+        indels,
+        min_quality,
+    );
+
+    if bam || header_format == HeaderFormat::Sam {
+        let barcode_parsers = [&barcode1_parser, &barcode2_parser];
+        check_sam_tags(
+            &barcode_parsers.into_iter().flatten().collect::<Vec<_>>(),
+            &index_parsers,
+            &barcode_combiner,
+        );
+    }
 
-    let barcode2 = pattern2.as_ref().map(|pat| {
-        BarcodeRegex::new(pat, max_error).unwrap_or_else(|e| {
-            eprintln!("{}", e);
+    let mut unmatched_writer = match (unmatched_out1, unmatched_out2) {
+        (Some(unmatched_out1), Some(unmatched_out2)) => Some(
+            FastqsWriter::new(
+                &unmatched_out1,
+                &unmatched_out2,
+                &output_compression,
+                threads,
+                force,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+        ),
+        (None, None) => None,
+        _ => {
+            eprintln!("{}", Error::UnmatchedOutputs);
             std::process::exit(1);
-        })
+        }
+    };
+
+    // Mates are interleaved in the single BAM
+    let mut writer = match out_fq2.filter(|_| !bam) {
+        Some(out_fq2) => FastqsWriter::new(&out_fq1, &out_fq2, &output_compression, threads, force),
+        None => FastqsWriter::new_bam(&out_fq1, threads, force),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
     });
+    writer.set_header_format(header_format);
 
     logger.message("Extracting barcodes from reads...");
 
+    let mut dropped = [0; DropReason::ALL.len()];
     loop {
-        let record_sets = reader.read_record_sets();
+        let record_sets = reader.read_record_sets().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
 
-        if let Ok((Some(records1), Some(records2))) = record_sets {
+        if let Some((records1, records2)) = record_sets {
             let records1 = records1.into_iter().collect::<Vec<_>>();
-            let records2 = records2.into_iter().collect::<Vec<_>>();
+            let index_records = read_index_records(&mut index_readers, &index_fastqs, &records1);
 
-            let new_reads = parse_pe_reads(
+            let (new_reads, dropped_reads) = parse_pe_reads(
                 &records1,
                 &records2,
-                &barcode1,
-                &barcode2,
-                skip_trimming,
-                rc_barcodes,
+                &barcode1_parser,
+                &barcode2_parser,
+                &barcode_combiner,
+                &match_policy,
+                &index_parsers,
+                &index_records,
             );
-
-            writer.write_all(new_reads).unwrap_or_else(|e| {
+            let unmatched_reads: Vec<(OwnedRecord, OwnedRecord)> = dropped_reads
+                .into_iter()
+                .map(|(reason, records)| {
+                    dropped[reason as usize] += 1;
+                    records
+                })
+                .collect();
+
+            writer.write_parsed_all(new_reads).unwrap_or_else(|e| {
                 eprintln!("{}", e);
                 std::process::exit(1);
             });
+            if let Some(unmatched_writer) = &mut unmatched_writer {
+                unmatched_writer
+                    .write_all(unmatched_reads)
+                    .unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    });
+            }
 
             logger.increment_progress(records1.len());
         } else {
             break;
         }
     }
+
+    check_index_readers_exhausted(&mut index_readers, &index_fastqs);
+
+    logger.summary(&drop_summary(&dropped));
+    log_whitelists_summary(&logger, &whitelist, &round_whitelists);
+    if rc_barcodes {
+        for (barcode_parser, name) in [
+            (&barcode1_parser, "Forward read orientations"),
+            (&barcode2_parser, "Reverse read orientations"),
+        ] {
+            if let Some(barcode_parser) = barcode_parser {
+                logger.summary(&barcode_parser.summary(name));
+            }
+        }
+    }
     logger.final_message();
 }
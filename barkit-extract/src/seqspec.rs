@@ -0,0 +1,499 @@
+#![allow(clippy::result_large_err)]
+
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::parse::get_reverse_complement;
+use crate::pattern::BarcodeType;
+use crate::preset::Preset;
+use crate::whitelist::WhitelistFile;
+
+/// Regex of any nucleotide in the seqspec patterns
+const NUCLEOTIDE_REGEX: &str = "[ATGCN]";
+
+/// seqspec assay with the library structure and sequenced reads
+#[derive(Debug, Deserialize)]
+struct Assay {
+    #[serde(default)]
+    assay_id: String,
+
+    #[serde(default)]
+    modalities: Vec<String>,
+
+    library_spec: Vec<Region>,
+
+    #[serde(default)]
+    sequence_spec: Vec<Read>,
+}
+
+/// Region of the library structure, which consists of the nested regions or is a leaf
+#[derive(Debug, Deserialize)]
+struct Region {
+    region_id: String,
+
+    region_type: String,
+
+    #[serde(default)]
+    sequence_type: String,
+
+    #[serde(default)]
+    sequence: String,
+
+    #[serde(default)]
+    min_len: usize,
+
+    #[serde(default)]
+    max_len: usize,
+
+    #[serde(default)]
+    onlist: Option<Onlist>,
+
+    #[serde(default)]
+    regions: Option<Vec<Region>>,
+}
+
+/// List of permitted barcodes of the region
+#[derive(Debug, Deserialize)]
+struct Onlist {
+    #[serde(default)]
+    filename: Option<String>,
+
+    #[serde(default)]
+    url: Option<String>,
+}
+
+/// Sequenced read starting next to the primer region
+#[derive(Debug, Deserialize)]
+struct Read {
+    read_id: String,
+
+    modality: String,
+
+    primer_id: String,
+
+    #[serde(default)]
+    max_len: usize,
+
+    #[serde(default = "default_strand")]
+    strand: String,
+}
+
+fn default_strand() -> String {
+    "pos".to_owned()
+}
+
+impl Region {
+    /// Returns leaf regions in the library order
+    fn leaves(&self) -> Vec<&Region> {
+        match &self.regions {
+            Some(regions) if !regions.is_empty() => {
+                regions.iter().flat_map(|region| region.leaves()).collect()
+            }
+            _ => vec![self],
+        }
+    }
+
+    /// Finds the nested region by its ID
+    fn find(&self, region_id: &str) -> Option<&Region> {
+        if self.region_id == region_id {
+            return Some(self);
+        }
+        self.regions
+            .iter()
+            .flatten()
+            .find_map(|region| region.find(region_id))
+    }
+
+    /// Returns the barcode type of the region, if it contains a barcode
+    fn barcode_type(&self) -> Option<BarcodeType> {
+        match self.region_type.as_str() {
+            "barcode" => Some(BarcodeType::Cell),
+            "umi" => Some(BarcodeType::Umi),
+            "index5" | "index7" => Some(BarcodeType::Sample),
+            _ => None,
+        }
+    }
+
+    /// Returns regex of the region length
+    fn len_regex(&self) -> String {
+        if self.min_len == self.max_len {
+            format!("{}{{{}}}", NUCLEOTIDE_REGEX, self.max_len)
+        } else {
+            format!("{}{{{},{}}}", NUCLEOTIDE_REGEX, self.min_len, self.max_len)
+        }
+    }
+}
+
+/// Loads the preset of barcode patterns and whitelists from the seqspec YAML file.
+/// Relative onlist paths are resolved against the directory of the file.
+pub fn load_preset(path: &str, modality: Option<&str>) -> Result<Preset, Error> {
+    let content = fs::read_to_string(path)?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    parse_preset(&content, modality, base_dir)
+}
+
+/// Builds the preset of barcode patterns and whitelists from the seqspec YAML content.
+/// Barcode patterns are built from regions covered by the forward and reverse reads of
+/// the modality up to the first template region (e.g. cDNA), barcode onlists are used
+/// as whitelists. Reads are assigned to mates by their IDs (e.g. `R1`, `read2`) or by
+/// their order among non-index reads. Index reads are not used.
+///
+/// Barcodes of negative strand reads are captured in the read orientation, while onlists
+/// list them in the library orientation, so their onlists are reverse complemented on loading.
+///
+/// # Example
+///
+/// ```
+/// use std::path::Path;
+///
+/// use barkit_extract::seqspec::parse_preset;
+///
+/// let preset = parse_preset(r#"
+/// !Assay
+/// assay_id: my-assay
+/// modalities: [rna]
+/// library_spec:
+/// - !Region
+///   region_id: rna
+///   region_type: rna
+///   regions:
+///   - !Region {region_id: r1_primer, region_type: custom_primer, sequence_type: fixed, sequence: ACACTCTTTCCCTACACGACGCTCTTCCGATCT, min_len: 33, max_len: 33}
+///   - !Region {region_id: barcode, region_type: barcode, sequence_type: onlist, min_len: 16, max_len: 16, onlist: !Onlist {filename: whitelist.txt}}
+///   - !Region {region_id: umi, region_type: umi, sequence_type: random, min_len: 12, max_len: 12}
+///   - !Region {region_id: cdna, region_type: cdna, sequence_type: random, min_len: 1, max_len: 98}
+/// sequence_spec:
+/// - !Read {read_id: R1, modality: rna, primer_id: r1_primer, min_len: 28, max_len: 28, strand: pos}
+/// "#, Some("rna"), Path::new("spec")).unwrap();
+///
+/// assert_eq!(vec!["^(?<CB>[ATGCN]{16})(?<UMI>[ATGCN]{12})"], preset.pattern1);
+/// assert_eq!(Some("spec/whitelist.txt".to_string()), preset.whitelist.map(|whitelist| whitelist.path));
+/// ```
+pub fn parse_preset(
+    content: &str,
+    modality: Option<&str>,
+    base_dir: &Path,
+) -> Result<Preset, Error> {
+    let assay: Assay =
+        serde_yaml::from_str(content).map_err(|e| Error::InvalidSeqspec(e.to_string()))?;
+
+    let modality = match (modality, assay.modalities.as_slice()) {
+        (Some(modality), _) => modality,
+        (None, [modality]) => modality.as_str(),
+        (None, _) => {
+            return Err(Error::InvalidSeqspec(
+                "modality must be provided for assays with several modalities".to_owned(),
+            ))
+        }
+    };
+    let library = assay
+        .library_spec
+        .iter()
+        .find(|region| region.region_id == modality || region.region_type == modality)
+        .ok_or(Error::UnknownModality(modality.to_owned()))?;
+    let leaves = library.leaves();
+
+    let barcode_leaves: Vec<&Region> = leaves
+        .iter()
+        .filter(|region| region.barcode_type() == Some(BarcodeType::Cell))
+        .copied()
+        .collect();
+
+    let mut patterns: [Option<String>; 2] = [None, None];
+    let mut negative_strand_barcodes = Vec::new();
+    let mut read_order = 0;
+    for read in assay
+        .sequence_spec
+        .iter()
+        .filter(|read| read.modality == modality)
+    {
+        let regions = get_read_regions(read, library, &leaves)?;
+        if is_index_read(&regions) {
+            continue;
+        }
+        read_order += 1;
+
+        let Some(pattern) = build_pattern(read, &regions, &barcode_leaves) else {
+            continue;
+        };
+        let mate = get_mate(&read.read_id).unwrap_or(read_order);
+        match patterns.get_mut(mate - 1) {
+            Some(mate_pattern @ None) => *mate_pattern = Some(pattern),
+            _ => {
+                return Err(Error::InvalidSeqspec(format!(
+                    "read {} of the {} modality contains barcodes, but it is not the first \
+                     or the second mate or its mate already contains barcodes",
+                    read.read_id, modality
+                )))
+            }
+        }
+        if read.strand == "neg" {
+            negative_strand_barcodes.extend(
+                regions
+                    .iter()
+                    .take_while(|region| {
+                        region.barcode_type().is_some() || region.sequence_type == "fixed"
+                    })
+                    .map(|region| &region.region_id),
+            );
+        }
+    }
+    let [pattern1, pattern2] = patterns;
+
+    let onlists: Vec<Option<WhitelistFile>> = barcode_leaves
+        .iter()
+        .map(|region| {
+            get_onlist_path(region, base_dir).map(|path| WhitelistFile {
+                path,
+                reverse_complement: negative_strand_barcodes.contains(&&region.region_id),
+            })
+        })
+        .collect();
+    let (whitelist, round_whitelists) = match onlists.as_slice() {
+        [onlist] => (onlist.clone(), Vec::new()),
+        _ if onlists.iter().all(Option::is_none) => (None, Vec::new()),
+        _ if onlists.iter().all(Option::is_some) => (None, onlists.into_iter().flatten().collect()),
+        _ => {
+            return Err(Error::InvalidSeqspec(
+                "either all or none of the barcode regions must have onlists".to_owned(),
+            ))
+        }
+    };
+
+    let preset = Preset {
+        name: assay.assay_id,
+        pattern1: pattern1.into_iter().collect(),
+        pattern2: pattern2.into_iter().collect(),
+        skip_trimming: false,
+        whitelist,
+        round_whitelists,
+    };
+    preset.validate()?;
+
+    Ok(preset)
+}
+
+/// Returns the mate (1 or 2) of the read by its ID, e.g. `R1`, `read2` or `rna_R1`
+fn get_mate(read_id: &str) -> Option<usize> {
+    let read_id = read_id.to_ascii_lowercase();
+    let (prefix, mate) = match (read_id.strip_suffix('1'), read_id.strip_suffix('2')) {
+        (Some(prefix), _) => (prefix, 1),
+        (_, Some(prefix)) => (prefix, 2),
+        _ => return None,
+    };
+    let prefix = prefix.strip_suffix('_').unwrap_or(prefix);
+    (prefix.ends_with("read") || prefix.ends_with('r')).then_some(mate)
+}
+
+/// Returns `true`, if the read starts with an index (`index5` or `index7`) region
+fn is_index_read(regions: &[&Region]) -> bool {
+    regions
+        .first()
+        .is_some_and(|region| region.barcode_type() == Some(BarcodeType::Sample))
+}
+
+/// Returns leaf regions covered by the read in the sequencing order.
+/// Reads of the negative strand cover regions preceding the primer in the reverse order.
+fn get_read_regions<'a>(
+    read: &Read,
+    library: &Region,
+    leaves: &[&'a Region],
+) -> Result<Vec<&'a Region>, Error> {
+    let primer_leaves = library
+        .find(&read.primer_id)
+        .ok_or(Error::InvalidSeqspec(format!(
+            "primer {} of read {} is not found",
+            read.primer_id, read.read_id
+        )))?
+        .leaves();
+    let position = |region: &Region| {
+        leaves
+            .iter()
+            .position(|leaf| leaf.region_id == region.region_id)
+    };
+    let (first, last) = match (
+        primer_leaves.first().and_then(|region| position(region)),
+        primer_leaves.last().and_then(|region| position(region)),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            return Err(Error::InvalidSeqspec(format!(
+                "primer {} of read {} is not found",
+                read.primer_id, read.read_id
+            )))
+        }
+    };
+
+    match read.strand.as_str() {
+        "pos" => Ok(leaves[last + 1..].to_vec()),
+        "neg" => Ok(leaves[..first].iter().rev().copied().collect()),
+        strand => Err(Error::InvalidSeqspec(format!(
+            "read {} has unexpected strand {}, expected pos or neg",
+            read.read_id, strand
+        ))),
+    }
+}
+
+/// Builds the barcode pattern of regions covered by the read up to the first template region.
+/// Returns `None`, if the read does not contain any barcode or contains only index barcodes.
+fn build_pattern(read: &Read, regions: &[&Region], barcode_leaves: &[&Region]) -> Option<String> {
+    let mut pattern = String::from("^");
+    let mut barcode_types = Vec::new();
+    let mut position = 0;
+
+    for region in regions {
+        position += region.max_len;
+        if read.max_len > 0 && position > read.max_len {
+            break;
+        }
+        match region.barcode_type() {
+            Some(BarcodeType::Cell) => {
+                let barcode_type = match barcode_leaves.len() {
+                    1 => BarcodeType::Cell,
+                    _ => BarcodeType::CellRound(
+                        barcode_leaves
+                            .iter()
+                            .position(|leaf| leaf.region_id == region.region_id)
+                            .map_or(0, |idx| idx + 1),
+                    ),
+                };
+                pattern.push_str(&format!("(?<{}>{})", barcode_type, region.len_regex()));
+                barcode_types.push(barcode_type);
+            }
+            Some(barcode_type) => {
+                pattern.push_str(&format!("(?<{}>{})", barcode_type, region.len_regex()));
+                barcode_types.push(barcode_type);
+            }
+            None if region.sequence_type == "fixed" => {
+                let sequence = match read.strand.as_str() {
+                    "neg" => get_reverse_complement(region.sequence.as_bytes()),
+                    _ => region.sequence.as_bytes().to_vec(),
+                };
+                pattern.push_str(&String::from_utf8_lossy(&sequence).to_lowercase());
+            }
+            None => break,
+        }
+    }
+
+    barcode_types
+        .iter()
+        .any(|barcode_type| *barcode_type != BarcodeType::Sample)
+        .then_some(pattern)
+}
+
+/// Returns path to the onlist file of the region relative to the base directory
+fn get_onlist_path(region: &Region, base_dir: &Path) -> Option<String> {
+    let onlist = region.onlist.as_ref()?;
+    let path = onlist
+        .url
+        .as_ref()
+        .filter(|url| !url.is_empty() && !url.contains("://"))
+        .or(onlist.filename.as_ref())?;
+    Some(base_dir.join(path).to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rstest::rstest;
+
+    use crate::error::Error;
+    use crate::seqspec::{get_mate, parse_preset};
+    use crate::whitelist::WhitelistFile;
+
+    const SPLIT_SEQ: &str = r#"
+!Assay
+assay_id: split-seq
+modalities: [rna]
+library_spec:
+- !Region
+  region_id: rna
+  region_type: rna
+  regions:
+  - !Region {region_id: cdna, region_type: cdna, sequence_type: random, min_len: 1, max_len: 100}
+  - !Region {region_id: bc1, region_type: barcode, sequence_type: onlist, min_len: 8, max_len: 8, onlist: !Onlist {filename: r1.txt}}
+  - !Region {region_id: linker1, region_type: linker, sequence_type: fixed, sequence: ATCCACGTG, min_len: 9, max_len: 9}
+  - !Region {region_id: bc2, region_type: barcode, sequence_type: onlist, min_len: 8, max_len: 8, onlist: !Onlist {filename: r2.txt}}
+  - !Region {region_id: umi, region_type: umi, sequence_type: random, min_len: 10, max_len: 10}
+  - !Region {region_id: r2_primer, region_type: custom_primer, sequence_type: fixed, sequence: AGATCGGAAG, min_len: 10, max_len: 10}
+sequence_spec:
+- !Read {read_id: R2, modality: rna, primer_id: r2_primer, min_len: 35, max_len: 35, strand: neg}
+"#;
+
+    #[test]
+    fn test_parse_preset_with_rounds() {
+        let preset = parse_preset(SPLIT_SEQ, None, Path::new("")).unwrap();
+        assert!(preset.pattern1.is_empty());
+        assert_eq!(
+            vec!["^(?<UMI>[ATGCN]{10})(?<CB2>[ATGCN]{8})cacgtggat(?<CB1>[ATGCN]{8})"],
+            preset.pattern2
+        );
+        assert_eq!(None, preset.whitelist);
+        assert_eq!(
+            vec![
+                WhitelistFile {
+                    path: "r1.txt".to_string(),
+                    reverse_complement: true,
+                },
+                WhitelistFile {
+                    path: "r2.txt".to_string(),
+                    reverse_complement: true,
+                },
+            ],
+            preset.round_whitelists
+        );
+    }
+
+    #[test]
+    fn test_parse_preset_by_read_order() {
+        let content = SPLIT_SEQ
+            .replace(
+                "  - !Region {region_id: cdna",
+                "  - !Region {region_id: r1_primer, region_type: custom_primer, sequence_type: fixed, sequence: CTCTTCCGATCT, min_len: 12, max_len: 12}\n  - !Region {region_id: cdna",
+            )
+            .replace(
+                "- !Read {read_id: R2",
+                "- !Read {read_id: first, modality: rna, primer_id: r1_primer, min_len: 35, max_len: 35, strand: pos}\n- !Read {read_id: second",
+            );
+        let preset = parse_preset(&content, None, Path::new("")).unwrap();
+        assert!(preset.pattern1.is_empty());
+        assert_eq!(
+            vec!["^(?<UMI>[ATGCN]{10})(?<CB2>[ATGCN]{8})cacgtggat(?<CB1>[ATGCN]{8})"],
+            preset.pattern2
+        );
+    }
+
+    #[rstest]
+    #[case("R1", Some(1))]
+    #[case("read2", Some(2))]
+    #[case("rna_R2", Some(2))]
+    #[case("read_1", Some(1))]
+    #[case("I1", None)]
+    #[case("R3", None)]
+    fn test_get_mate(#[case] read_id: &str, #[case] expected: Option<usize>) {
+        assert_eq!(expected, get_mate(read_id));
+    }
+
+    #[rstest]
+    #[case(Some("atac"))]
+    fn test_parse_preset_unknown_modality(#[case] modality: Option<&str>) {
+        assert!(matches!(
+            parse_preset(SPLIT_SEQ, modality, Path::new("")),
+            Err(Error::UnknownModality(_))
+        ));
+    }
+
+    #[rstest]
+    #[case("library_spec: []\nsequence_spec: 1")]
+    #[case(&SPLIT_SEQ.replace("primer_id: r2_primer", "primer_id: unknown"))]
+    #[case(&SPLIT_SEQ.replace("strand: neg", "strand: both"))]
+    fn test_parse_invalid_seqspec(#[case] content: &str) {
+        assert!(matches!(
+            parse_preset(content, Some("rna"), Path::new("")),
+            Err(Error::InvalidSeqspec(_))
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::parse::get_reverse_complement;
+
+/// Gzip magic bytes
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+
+/// Nucleotides used to generate barcodes within Hamming distance 1
+const NUCLEOTIDES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Result of the barcode correction against the whitelist
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Correction {
+    /// Barcode is found in the whitelist as is
+    Valid,
+
+    /// Barcode is corrected to the whitelist entry
+    Corrected(Vec<u8>),
+
+    /// Barcode can not be assigned to a whitelist entry
+    Invalid,
+}
+
+/// Whitelist file and orientation of its barcodes
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(from = "String")]
+pub struct WhitelistFile {
+    /// Path to the plain or gzip-compressed file
+    pub path: String,
+
+    /// If `true`, barcodes are reverse complemented on loading, e.g. onlists of barcodes
+    /// read from the negative strand, which are listed in the library orientation
+    pub reverse_complement: bool,
+}
+
+impl From<String> for WhitelistFile {
+    fn from(path: String) -> Self {
+        Self {
+            path,
+            reverse_complement: false,
+        }
+    }
+}
+
+/// Whitelist of the cell barcodes with statistics of the barcodes correction
+#[derive(Debug)]
+pub struct Whitelist {
+    /// Whitelisted barcodes in upper case
+    barcodes: HashSet<Vec<u8>>,
+
+    /// Number of barcodes found in the whitelist as is
+    valid: AtomicUsize,
+
+    /// Number of barcodes corrected to a whitelist entry
+    corrected: AtomicUsize,
+
+    /// Number of barcodes not assigned to a whitelist entry
+    invalid: AtomicUsize,
+}
+
+impl Whitelist {
+    /// Creates `Whitelist` instance from the barcodes
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::whitelist::Whitelist;
+    ///
+    /// let whitelist = Whitelist::new(["AAAA", "CCCC"]);
+    /// ```
+    pub fn new<I, S>(barcodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            barcodes: barcodes
+                .into_iter()
+                .map(|barcode| barcode.as_ref().trim().to_ascii_uppercase().into_bytes())
+                .filter(|barcode| !barcode.is_empty())
+                .collect(),
+            valid: AtomicUsize::new(0),
+            corrected: AtomicUsize::new(0),
+            invalid: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reads whitelist from the plain or gzip-compressed file with one barcode per line.
+    /// Only the first column of tab-separated lines is used as a barcode.
+    pub fn from_file(whitelist_file: &WhitelistFile) -> Result<Self, Error> {
+        let path = whitelist_file.path.as_str();
+        let mut file = BufReader::new(File::open(path)?);
+        let reader: Box<dyn BufRead> = if file.fill_buf()?.starts_with(&GZIP_MAGIC_BYTES) {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(file)
+        };
+
+        let mut barcodes = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(barcode) = line.split('\t').next() {
+                barcodes.push(match whitelist_file.reverse_complement {
+                    true => {
+                        String::from_utf8_lossy(&get_reverse_complement(barcode.trim().as_bytes()))
+                            .into_owned()
+                    }
+                    false => barcode.to_owned(),
+                });
+            }
+        }
+
+        let whitelist = Self::new(barcodes);
+        if whitelist.barcodes.is_empty() {
+            return Err(Error::EmptyWhitelist(path.to_owned()));
+        }
+        Ok(whitelist)
+    }
+
+    /// Corrects the barcode to the whitelist entry within Hamming distance 1.
+    /// If several entries are found, the one with a substitution in the base
+    /// with the lowest quality is selected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::whitelist::{Correction, Whitelist};
+    ///
+    /// let whitelist = Whitelist::new(["AAAA", "CCCC"]);
+    ///
+    /// assert_eq!(Correction::Valid, whitelist.correct(b"AAAA", b"IIII"));
+    /// assert_eq!(Correction::Corrected(b"CCCC".to_vec()), whitelist.correct(b"CCNC", b"II#I"));
+    /// assert_eq!(Correction::Invalid, whitelist.correct(b"AACC", b"IIII"));
+    /// ```
+    pub fn correct(&self, seq: &[u8], qual: &[u8]) -> Correction {
+        let correction = self.find_correction(seq, qual);
+
+        let counter = match correction {
+            Correction::Valid => &self.valid,
+            Correction::Corrected(_) => &self.corrected,
+            Correction::Invalid => &self.invalid,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        correction
+    }
+
+    fn find_correction(&self, seq: &[u8], qual: &[u8]) -> Correction {
+        let seq = seq.to_ascii_uppercase();
+        if self.barcodes.contains(&seq) {
+            return Correction::Valid;
+        }
+
+        // Whitelist entries within Hamming distance 1 and the substituted positions
+        let mut candidates: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut candidate = seq.clone();
+        for (idx, base) in seq.iter().enumerate() {
+            for nucleotide in NUCLEOTIDES.iter().filter(|nucleotide| *nucleotide != base) {
+                candidate[idx] = *nucleotide;
+                if self.barcodes.contains(&candidate) {
+                    candidates.push((idx, candidate.clone()));
+                }
+            }
+            candidate[idx] = *base;
+        }
+
+        let Some(min_qual) = candidates
+            .iter()
+            .map(|(idx, _)| qual.get(*idx).copied().unwrap_or(u8::MAX))
+            .min()
+        else {
+            return Correction::Invalid;
+        };
+
+        let mut best_candidates = candidates
+            .into_iter()
+            .filter(|(idx, _)| qual.get(*idx).copied().unwrap_or(u8::MAX) == min_qual);
+
+        match (best_candidates.next(), best_candidates.next()) {
+            (Some((_, barcode)), None) => Correction::Corrected(barcode),
+            _ => Correction::Invalid,
+        }
+    }
+
+    /// Returns summary of the barcodes correction, e.g. for `Cell barcodes`
+    pub fn summary(&self, name: &str) -> String {
+        let valid = self.valid.load(Ordering::Relaxed);
+        let corrected = self.corrected.load(Ordering::Relaxed);
+        let invalid = self.invalid.load(Ordering::Relaxed);
+        let total = (valid + corrected + invalid).max(1) as f64;
+
+        format!(
+            "{}: {} valid ({:.2}%), {} corrected ({:.2}%), {} not in whitelist ({:.2}%)",
+            name,
+            valid,
+            100.0 * valid as f64 / total,
+            corrected,
+            100.0 * corrected as f64 / total,
+            invalid,
+            100.0 * invalid as f64 / total,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::whitelist::{Correction, Whitelist};
+
+    #[rstest]
+    #[case(Correction::Valid, b"ACGT", b"IIII")]
+    #[case(Correction::Valid, b"acgt", b"IIII")]
+    #[case(Correction::Corrected(b"ACGT".to_vec()), b"ACNT", b"IIII")]
+    #[case(Correction::Corrected(b"ACGT".to_vec()), b"ACCT", b"II#I")]
+    #[case(Correction::Corrected(b"TCGA".to_vec()), b"ACGA", b"#III")]
+    #[case(Correction::Corrected(b"ACGT".to_vec()), b"ACGA", b"III#")]
+    #[case(Correction::Invalid, b"ACGA", b"IIII")]
+    #[case(Correction::Invalid, b"GGGG", b"IIII")]
+    #[case(Correction::Invalid, b"ACG", b"III")]
+    fn test_correct(#[case] expected: Correction, #[case] seq: &[u8], #[case] qual: &[u8]) {
+        let whitelist = Whitelist::new(["ACGT", "TCGA", "CCCC"]);
+        assert_eq!(expected, whitelist.correct(seq, qual));
+    }
+}
@@ -0,0 +1,217 @@
+#![allow(clippy::result_large_err)]
+
+use barkit_extract::error::Error;
+use barkit_extract::pattern::{
+    BarcodeCaptures, BarcodePattern, BarcodeRegex, BarcodeType, MaxError, PatternSegment,
+};
+use regex::bytes::RegexBuilder;
+
+/// Min and max compiled regex size limits (as powers of two) used to estimate the compiled size
+const COMPILED_SIZE_LIMITS: std::ops::RangeInclusive<u32> = 10..=30;
+
+/// Number of regex alternatives of adapters, above which the pattern is considered complex
+const MAX_ADAPTER_VARIANTS: usize = 10_000;
+
+/// Nucleotides allowed in example sequences
+const NUCLEOTIDES: &[u8] = b"ACGTN";
+
+/// Explanation of the barcode pattern
+pub struct PatternExplanation {
+    /// Parts of the pattern matched by regex and by the approximate adapter matcher
+    pub segments: Vec<PatternSegment>,
+
+    /// Capture groups detected in the pattern
+    pub capture_groups: Vec<BarcodeType>,
+
+    /// Regex pattern, where all adapters are expanded into alternations of sequences with errors
+    pub pattern_with_errors: String,
+
+    /// Adapters with the number of their regex alternatives
+    pub adapter_variants: Vec<(String, usize)>,
+
+    /// Estimated size of the compiled regex pattern with errors in bytes
+    /// (`None`, if it exceeds the max limit)
+    pub compiled_size: Option<usize>,
+
+    /// Possible problems of the pattern
+    pub warnings: Vec<String>,
+
+    /// Compiled pattern to test sequences
+    barcode_regex: BarcodeRegex,
+}
+
+impl PatternExplanation {
+    /// Creates `PatternExplanation` instance
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use barkit_extract::pattern::{BarcodeType, MaxError};
+    /// use barkit_pattern::explain::PatternExplanation;
+    ///
+    /// let explanation = PatternExplanation::new("^atgc(?<UMI>[ATGCN]{12})", MaxError::Count(1), false).unwrap();
+    ///
+    /// assert_eq!(vec![BarcodeType::Umi], explanation.capture_groups);
+    /// assert_eq!("^(ATG.|AT.C|A.GC|.TGC)(?<UMI>[ATGCN]{12})", explanation.pattern_with_errors);
+    /// ```
+    pub fn new(pattern: &str, max_error: MaxError, indels: bool) -> Result<Self, Error> {
+        let barcode_regex = BarcodeRegex::new(pattern, max_error, indels, None, None)?;
+        let barcode_pattern = BarcodePattern::new(pattern, &max_error, indels)?;
+
+        let segments = barcode_pattern.get_segments()?;
+        let adapter_variants = barcode_pattern.get_adapter_variants()?;
+        let pattern_with_errors = barcode_pattern.get_pattern_with_errors()?;
+        let compiled_size = get_compiled_size(&pattern_with_errors);
+        let warnings = get_warnings(&segments, &adapter_variants, compiled_size);
+
+        Ok(Self {
+            segments,
+            capture_groups: barcode_regex.get_barcode_types(),
+            pattern_with_errors,
+            adapter_variants,
+            compiled_size,
+            warnings,
+            barcode_regex,
+        })
+    }
+
+    /// Matches the pattern to the example sequence
+    pub fn test(&self, sequence: &str) -> Option<BarcodeCaptures> {
+        self.barcode_regex
+            .get_captures(sequence.as_bytes(), &[])
+            .ok()
+    }
+}
+
+/// Checks that the example sequence contains only nucleotides, so that positions of
+/// captured bytes are positions of its characters
+///
+/// # Example
+///
+/// ```
+/// use barkit_pattern::explain::check_sequence;
+///
+/// assert!(check_sequence("ATGCnn").is_ok());
+/// assert!(check_sequence("ATGC-N").is_err());
+/// ```
+pub fn check_sequence(sequence: &str) -> Result<(), Error> {
+    if !sequence
+        .bytes()
+        .all(|base| NUCLEOTIDES.contains(&base.to_ascii_uppercase()))
+    {
+        return Err(Error::InvalidSequence(sequence.to_owned()));
+    }
+    Ok(())
+}
+
+/// Estimates size of the compiled regex pattern by increasing the size limit
+/// until the pattern is compiled
+fn get_compiled_size(pattern: &str) -> Option<usize> {
+    COMPILED_SIZE_LIMITS
+        .map(|power| 1usize << power)
+        .find(|size_limit| {
+            RegexBuilder::new(pattern)
+                .size_limit(*size_limit)
+                .build()
+                .is_ok()
+        })
+}
+
+/// Returns warnings about possible problems of the pattern
+fn get_warnings(
+    segments: &[PatternSegment],
+    adapter_variants: &[(String, usize)],
+    compiled_size: Option<usize>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for segment in segments {
+        if let PatternSegment::Adapter {
+            sequence,
+            max_error,
+        } = segment
+        {
+            if *max_error >= sequence.len() {
+                warnings.push(format!(
+                    "Adapter {} matches any sequence of its length with {} errors",
+                    sequence, max_error
+                ));
+            }
+        }
+    }
+
+    let approximate_adapters = segments
+        .iter()
+        .filter(|segment| matches!(segment, PatternSegment::Adapter { .. }))
+        .count();
+    if approximate_adapters < adapter_variants.len() {
+        warnings.push(format!(
+            "{} of {} adapters are expanded into regex alternatives, place adapters outside of groups, quantifiers and alternations to match them faster",
+            adapter_variants.len() - approximate_adapters,
+            adapter_variants.len()
+        ));
+    }
+
+    let total_variants: usize = adapter_variants.iter().map(|(_, variants)| variants).sum();
+    if total_variants > MAX_ADAPTER_VARIANTS {
+        warnings.push(format!(
+            "Adapters are expanded into {} regex alternatives, consider decreasing max error",
+            total_variants
+        ));
+    }
+
+    if compiled_size.is_none() {
+        warnings.push(format!(
+            "Compiled regex pattern with errors exceeds {} bytes",
+            1usize << COMPILED_SIZE_LIMITS.end()
+        ));
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use barkit_extract::error::Error;
+    use barkit_extract::pattern::MaxError;
+
+    use crate::explain::{check_sequence, PatternExplanation};
+
+    #[rstest]
+    #[case("^atgc(?<UMI>[ATGCN]{4})", 1, 0)]
+    #[case("^atgc{e=4}(?<UMI>[ATGCN]{4})", 1, 1)]
+    #[case("^(atgc)(?<UMI>[ATGCN]{4})", 1, 1)]
+    #[case("^(?<UMI>[ATGCN]{4})", 1, 0)]
+    fn test_warnings(#[case] pattern: &str, #[case] max_error: usize, #[case] warnings: usize) {
+        let explanation =
+            PatternExplanation::new(pattern, MaxError::Count(max_error), false).unwrap();
+        assert_eq!(warnings, explanation.warnings.len());
+    }
+
+    #[rstest]
+    #[case("ATGCAAAA\u{e9}")]
+    #[case("ATGC AAAA")]
+    #[case("ATGCXAAA")]
+    fn test_invalid_sequence(#[case] sequence: &str) {
+        assert!(matches!(
+            check_sequence(sequence),
+            Err(Error::InvalidSequence(_))
+        ));
+    }
+
+    #[rstest]
+    #[case("ATGCAAAACC", Some(b"AAAA".as_slice()))]
+    #[case("ATCCAAAACC", Some(b"AAAA".as_slice()))]
+    #[case("TTCCAAAACC", None)]
+    fn test_sequence(#[case] sequence: &str, #[case] umi: Option<&[u8]>) {
+        let explanation =
+            PatternExplanation::new("^atgc(?<UMI>[ATGCN]{4})", MaxError::Count(1), false).unwrap();
+        let captures = explanation.test(sequence);
+        assert_eq!(
+            umi,
+            captures.map(|captures| &sequence.as_bytes()[captures.name("UMI").unwrap()])
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use barkit_extract::pattern::{BarcodeCaptures, MaxError, PatternSegment};
+use console::{style, Color};
+
+use crate::explain::{self, PatternExplanation};
+
+/// Max number of displayed characters of the regex pattern with errors
+const MAX_DISPLAYED_LENGTH: usize = 1000;
+
+/// Colours of the capture groups in the match visualisation
+const GROUP_COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Green,
+    Color::Blue,
+    Color::Red,
+];
+
+pub fn run(pattern: String, max_error: MaxError, indels: bool, sequences: Vec<String>) {
+    let explanation = PatternExplanation::new(&pattern, max_error, indels).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    for sequence in &sequences {
+        explain::check_sequence(sequence).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    println!("{} {}", style("Pattern:").bold(), pattern);
+
+    println!("{}", style("Segments:").bold());
+    for segment in &explanation.segments {
+        match segment {
+            PatternSegment::Regex(regex) => println!("  regex    {}", regex),
+            PatternSegment::Adapter {
+                sequence,
+                max_error,
+            } => println!("  adapter  {} (max error {})", sequence, max_error),
+        }
+    }
+
+    println!(
+        "{} {}",
+        style("Capture groups:").bold(),
+        explanation
+            .capture_groups
+            .iter()
+            .map(|barcode_type| barcode_type.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    println!("{}", style("Adapters:").bold());
+    for (adapter, variants) in &explanation.adapter_variants {
+        println!("  {} ({} regex alternatives)", adapter, variants);
+    }
+
+    println!(
+        "{} {}",
+        style("Pattern with errors:").bold(),
+        truncate(&explanation.pattern_with_errors, MAX_DISPLAYED_LENGTH)
+    );
+
+    println!(
+        "{} {} characters, compiled size {}",
+        style("Complexity:").bold(),
+        explanation.pattern_with_errors.len(),
+        explanation
+            .compiled_size
+            .map_or("is too large".to_string(), |size| format!(
+                "≤ {} KiB",
+                size / 1024
+            ))
+    );
+
+    for warning in &explanation.warnings {
+        println!("{} {}", style("Warning:").yellow().bold(), warning);
+    }
+
+    if !sequences.is_empty() {
+        println!("{}", style("Sequences:").bold());
+    }
+    for sequence in &sequences {
+        match explanation.test(sequence) {
+            Some(captures) => println!(
+                "  {}  {}",
+                visualise_match(sequence, &captures, &explanation),
+                describe_match(sequence, &captures, &explanation)
+            ),
+            None => println!("  {}  {}", style(sequence).dim(), style("no match").red()),
+        }
+    }
+}
+
+/// Colours capture groups of the sequence and underlines the full match
+fn visualise_match(
+    sequence: &str,
+    captures: &BarcodeCaptures,
+    explanation: &PatternExplanation,
+) -> String {
+    let groups: Vec<_> = explanation
+        .capture_groups
+        .iter()
+        .filter_map(|barcode_type| captures.name(&barcode_type.to_string()))
+        .collect();
+    let full_match = captures.get_match();
+
+    sequence
+        .bytes()
+        .enumerate()
+        .map(|(idx, base)| {
+            let base = base as char;
+            let styled = match groups.iter().position(|group| group.contains(&idx)) {
+                Some(group_idx) => style(base)
+                    .fg(GROUP_COLORS[group_idx % GROUP_COLORS.len()])
+                    .bold(),
+                None if full_match.contains(&idx) => style(base).underlined(),
+                None => style(base).dim(),
+            };
+            styled.to_string()
+        })
+        .collect()
+}
+
+/// Lists captured barcodes and the number of errors
+fn describe_match(
+    sequence: &str,
+    captures: &BarcodeCaptures,
+    explanation: &PatternExplanation,
+) -> String {
+    let mut description: Vec<String> = explanation
+        .capture_groups
+        .iter()
+        .filter_map(|barcode_type| {
+            let name = barcode_type.to_string();
+            captures.name(&name).map(|range| {
+                format!(
+                    "{}:{}",
+                    name,
+                    String::from_utf8_lossy(&sequence.as_bytes()[range])
+                )
+            })
+        })
+        .collect();
+    description.push(format!("errors:{}", captures.get_errors()));
+    description.join(" ")
+}
+
+/// Truncates the text to the max number of characters
+fn truncate(text: &str, max_len: usize) -> String {
+    match text.char_indices().nth(max_len) {
+        Some((idx, _)) => format!("{}... ({} more characters)", &text[..idx], text.len() - idx),
+        None => text.to_string(),
+    }
+}
@@ -20,7 +20,9 @@ echo "Preparing $1..."
 msg="# managed by release.sh"
 sed -E -i "s/^version = .* $msg$/version = \"${1#v}\" $msg/" barkit*/Cargo.toml
 sed -E -i "s/^version = .* $msg$/version = \"${1#v}\" $msg/" Cargo.toml
-sed -E -i "s/(barkit-extract = \{ version = \")[^\"]+/\1${1#v}/" Cargo.toml
+sed -E -i "s/(barkit-extract = \{ version = \")[^\"]+/\1${1#v}/" Cargo.toml barkit-pattern/Cargo.toml barkit-demux/Cargo.toml
+sed -E -i "s/(barkit-pattern = \{ version = \")[^\"]+/\1${1#v}/" Cargo.toml
+sed -E -i "s/(barkit-demux = \{ version = \")[^\"]+/\1${1#v}/" Cargo.toml
 
 # update the changelog
 git cliff --config cliff.toml --tag "$1" >CHANGELOG.md
@@ -1,4 +1,8 @@
-use clap::{command, ArgAction, Parser, Subcommand};
+use barkit_extract::header::HeaderFormat;
+use barkit_extract::notation::PatternSyntax;
+use barkit_extract::parse::{MatchRequirement, MateOrder, PatternSelection};
+use barkit_extract::pattern::SearchWindow;
+use clap::{ArgAction, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -24,6 +28,7 @@ pub struct Args {
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     /// Extract barcode nucleotide sequence according to a specified regex pattern
     #[clap(arg_required_else_help = true)]
@@ -37,12 +42,82 @@ pub enum Commands {
         #[clap(flatten)]
         patterns: PatternsGroup,
 
+        #[clap(flatten)]
+        index_fastqs: IndexGroup,
+
         #[clap(flatten)]
         compression: CompressionGroup,
 
         #[clap(flatten)]
         additional_params: AdditionalParamsGroup,
     },
+
+    /// Lint, explain and test barcode regex pattern
+    #[clap(arg_required_else_help = true)]
+    Pattern {
+        /// Barcode pattern
+        #[arg(short = 'p', long)]
+        pattern: String,
+
+        /// Max error (mismatch) between provided pattern and read sequence
+        #[arg(short = 'e', long, default_value = "1")]
+        max_error: usize,
+
+        /// Max error rate (errors per nucleotide) of adapters, used instead of absolute max errors
+        #[arg(short = 'E', long, value_parser = parse_error_rate)]
+        max_error_rate: Option<f64>,
+
+        /// Count insertions and deletions in adapters as errors too (edit distance)
+        #[arg(short = 'd', long, action=ArgAction::SetTrue)]
+        indels: bool,
+
+        /// Example sequences to match the pattern against
+        #[arg(value_name = "SEQUENCE")]
+        sequences: Vec<String>,
+    },
+
+    /// Demultiplex reads into samples by index or inline barcodes of a sample sheet
+    #[clap(arg_required_else_help = true)]
+    Demux {
+        /// Input forward FASTQ file
+        #[arg(short = '1', long, value_name = "IN_FASTQ1")]
+        fq1: String,
+
+        /// Input reverse FASTQ file
+        #[arg(short = '2', long, value_name = "IN_FASTQ2")]
+        fq2: Option<String>,
+
+        /// Input first index FASTQ file (I1) with i7 barcodes, otherwise they are taken from read headers
+        #[arg(long, value_name = "IN_INDEX1")]
+        index1: Option<String>,
+
+        /// Input second index FASTQ file (I2) with i5 barcodes, otherwise they are taken from read headers
+        #[arg(long, value_name = "IN_INDEX2")]
+        index2: Option<String>,
+
+        /// Sample sheet (comma or tab separated) with sample, i7, i5 and inline barcode columns
+        #[arg(short = 's', long, value_name = "FILE")]
+        sample_sheet: String,
+
+        /// Output directory for FASTQ files of samples, undetermined reads and the read counts table
+        #[arg(short = 'o', long, value_name = "OUT_DIR")]
+        out_dir: String,
+
+        /// Barcode pattern of forward reads with the SB capture group of inline barcodes
+        #[arg(short = 'p', long)]
+        inline_pattern: Option<String>,
+
+        /// Max mismatches between each barcode of the sample and the read (also the max error of adapters in the inline barcode pattern)
+        #[arg(short = 'n', long, default_value = "1")]
+        max_mismatches: usize,
+
+        /// Match i7 and i5 barcodes independently, assign reads only if both point to the same sample and write the index hopping matrix
+        #[arg(short = 'd', long, action=ArgAction::SetTrue)]
+        dual_index: bool,
+
+        #[clap(flatten)]
+        compression: CompressionGroup,
+    },
 }
 
 #[derive(Debug, clap::Args)]
@@ -52,7 +127,7 @@ pub struct InputsGroup {
     pub fq1: String,
 
     /// Input reverse FASTQ file
-    #[arg(short = '2', long, value_name = "IN_FASTQ2", requires_all = ["fq1", "out_fq2"])]
+    #[arg(short = '2', long, value_name = "IN_FASTQ2", requires_all = ["fq1", "mate_output"])]
     pub fq2: Option<String>,
 }
 
@@ -63,20 +138,129 @@ pub struct OutputsGroup {
     pub out_fq1: String,
 
     /// Output reverse FASTQ file
-    #[arg(short = 'O', long, value_name = "OUT_FASTQ2", requires = "out_fq1")]
+    #[arg(
+        short = 'O',
+        long,
+        value_name = "OUT_FASTQ2",
+        requires = "out_fq1",
+        group = "mate_output"
+    )]
     pub out_fq2: Option<String>,
+
+    /// Write reads into the unaligned BAM (--out-fq1) with barcodes in SAM tags, mates are interleaved
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        group = "mate_output",
+        conflicts_with_all = ["gz", "bgz", "mgz", "lz4"]
+    )]
+    pub bam: bool,
+
+    /// Format of barcodes in read headers: barkit, umi-tools, illumina, sam or a template, e.g. "{name}_{UMI} CB:Z:{CB}"
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "barkit",
+        value_parser = parse_header_format,
+        conflicts_with = "bam"
+    )]
+    pub header_format: HeaderFormat,
+
+    /// Output forward FASTQ file of reads not matching the patterns
+    #[arg(long, value_name = "UNMATCHED_FASTQ1")]
+    pub unmatched_out1: Option<String>,
+
+    /// Output reverse FASTQ file of mates not matching the patterns
+    #[arg(long, value_name = "UNMATCHED_FASTQ2", requires_all = ["fq2", "unmatched_out1"])]
+    pub unmatched_out2: Option<String>,
 }
 
 #[derive(Debug, clap::Args)]
 #[group(required = true, multiple = true)]
 pub struct PatternsGroup {
-    /// Barcode pattern of forward reads
+    /// Barcode pattern of forward reads (can be repeated to try alternative patterns in order)
     #[arg(short = 'p', long, requires = "fq1")]
-    pub pattern1: Option<String>,
+    pub pattern1: Vec<String>,
 
-    /// Barcode pattern of reverse reads
+    /// Barcode pattern of reverse reads (can be repeated to try alternative patterns in order)
     #[arg(short = 'P', long, requires = "fq2")]
-    pub pattern2: Option<String>,
+    pub pattern2: Vec<String>,
+
+    /// File with alternative barcode patterns of forward reads (one per line)
+    #[arg(long, value_name = "FILE", requires = "fq1")]
+    pub patterns_file1: Option<String>,
+
+    /// File with alternative barcode patterns of reverse reads (one per line)
+    #[arg(long, value_name = "FILE", requires = "fq2")]
+    pub patterns_file2: Option<String>,
+
+    /// Syntax of the barcode patterns and patterns files
+    #[arg(long, value_enum, default_value = "regex", conflicts_with_all = ["preset", "seqspec"])]
+    pub pattern_syntax: PatternSyntax,
+
+    /// Predefined barcode patterns of the protocol (10x-3p-v2, 10x-3p-v3, 10x-5p, drop-seq, indrop, cel-seq2, qiaseq-dna or user-defined)
+    #[arg(
+        long,
+        conflicts_with_all = ["pattern1", "pattern2", "patterns_file1", "patterns_file2"]
+    )]
+    pub preset: Option<String>,
+
+    /// TOML file with user-defined presets
+    #[arg(long, value_name = "FILE", requires = "preset")]
+    pub presets_file: Option<String>,
+
+    /// seqspec YAML file describing the library structure to build barcode patterns and whitelists from
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["pattern1", "pattern2", "patterns_file1", "patterns_file2", "preset"]
+    )]
+    pub seqspec: Option<String>,
+
+    /// Modality of the seqspec assay (e.g. rna or atac), required for multimodal assays
+    #[arg(long, requires = "seqspec")]
+    pub modality: Option<String>,
+
+    /// How to select one of the alternative patterns, if several of them match a read
+    #[arg(long, value_enum, default_value = "first")]
+    pub pattern_selection: PatternSelection,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct IndexGroup {
+    /// Input first index FASTQ file (I1) read in lockstep with the input FASTQs
+    #[arg(long, value_name = "IN_INDEX1", requires = "fq1")]
+    pub index1: Option<String>,
+
+    /// Input second index FASTQ file (I2) read in lockstep with the input FASTQs
+    #[arg(long, value_name = "IN_INDEX2", requires = "index1")]
+    pub index2: Option<String>,
+
+    /// Barcode pattern of first index reads (can be repeated to try alternative patterns in order)
+    #[arg(long, requires = "index1")]
+    pub index_pattern1: Vec<String>,
+
+    /// Barcode pattern of second index reads (can be repeated to try alternative patterns in order)
+    #[arg(long, requires = "index2")]
+    pub index_pattern2: Vec<String>,
+
+    /// Tag to copy the whole sequence of first index reads into
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "index1",
+        conflicts_with = "index_pattern1"
+    )]
+    pub index_tag1: Option<String>,
+
+    /// Tag to copy the whole sequence of second index reads into
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "index2",
+        conflicts_with = "index_pattern2"
+    )]
+    pub index_tag2: Option<String>,
 }
 
 #[derive(Debug, clap::Args)]
@@ -104,11 +288,114 @@ pub struct AdditionalParamsGroup {
     #[arg(short = 'r', long, action=ArgAction::SetTrue)]
     pub rc_barcodes: bool,
 
+    /// Write reads matched in reverse complement in the pattern orientation
+    #[arg(long, action = ArgAction::SetTrue, requires = "rc_barcodes")]
+    pub reorient: bool,
+
     /// Skip trimming the adapter sequence from the read
     #[arg(short = 's', long, action=ArgAction::SetTrue)]
     pub skip_trimming: bool,
 
+    /// Whitelist of cell barcodes (plain or gzip-compressed, one per line) to correct CB
+    #[arg(short = 'w', long, value_name = "FILE")]
+    pub whitelist: Option<String>,
+
+    /// Whitelists of split-pool round cell barcodes (CB1, CB2, ...) in order of rounds
+    #[arg(long, value_name = "FILE", value_delimiter = ',')]
+    pub round_whitelists: Vec<String>,
+
+    /// Pad variable-length barcodes (e.g. [ATGCN]{8,11}) with N to their max length
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub pad_barcodes: bool,
+
+    /// Barcodes captured in both mates to combine into a single tag written to both headers
+    #[arg(long, value_name = "NAME", value_delimiter = ',', requires = "fq2")]
+    pub combine_barcodes: Vec<String>,
+
+    /// Order of mates in combined barcodes
+    #[arg(long, value_enum, default_value = "r1-r2")]
+    pub combine_order: MateOrder,
+
+    /// Separator between barcodes of mates in combined barcodes
+    #[arg(long, default_value = "-")]
+    pub combine_separator: String,
+
+    /// Which of the mate patterns must match to keep the pair (others go to unmatched outputs)
+    #[arg(long, value_enum, default_value = "any", requires = "fq2")]
+    pub match_requirement: MatchRequirement,
+
+    /// Mates (1 or 2) with optional patterns, that are extracted if found but never drop the pair
+    #[arg(
+        long,
+        value_name = "MATE",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u8).range(1..=2),
+        requires = "fq2"
+    )]
+    pub optional_patterns: Vec<u8>,
+
     /// Max error (mismatch) between provided pattern and read sequence
     #[arg(short = 'e', long, default_value = "1")]
     pub max_error: usize,
+
+    /// Max error of adapters in the forward reads pattern (overrides --max-error)
+    #[arg(long, value_name = "MAX_ERROR")]
+    pub max_error1: Option<usize>,
+
+    /// Max error of adapters in the reverse reads pattern (overrides --max-error)
+    #[arg(long, value_name = "MAX_ERROR")]
+    pub max_error2: Option<usize>,
+
+    /// Max error rate (errors per nucleotide) of adapters, used instead of absolute max errors
+    #[arg(
+        short = 'E',
+        long,
+        value_parser = parse_error_rate,
+        conflicts_with_all = ["max_error1", "max_error2"]
+    )]
+    pub max_error_rate: Option<f64>,
+
+    /// Part of forward reads to search patterns in, e.g. 0..40 or -30.. for the last 30 bases
+    /// (one window for all patterns or one per pattern in order)
+    #[arg(long, value_name = "START..END", value_parser = parse_search_window, value_delimiter = ',', allow_hyphen_values = true)]
+    pub window1: Vec<SearchWindow>,
+
+    /// Part of reverse reads to search patterns in, e.g. 0..40 or -30.. for the last 30 bases
+    /// (one window for all patterns or one per pattern in order)
+    #[arg(long, value_name = "START..END", value_parser = parse_search_window, value_delimiter = ',', allow_hyphen_values = true)]
+    pub window2: Vec<SearchWindow>,
+
+    /// Count insertions and deletions in adapters as errors too (edit distance)
+    #[arg(short = 'd', long, action=ArgAction::SetTrue)]
+    pub indels: bool,
+
+    /// Min Phred quality of read bases, lower quality bases match any adapter base without errors
+    #[arg(long, value_name = "QUALITY")]
+    pub min_quality: Option<u8>,
+}
+
+/// Parses search window in the `<start>..<end>` format
+fn parse_search_window(value: &str) -> Result<SearchWindow, String> {
+    value
+        .parse()
+        .map_err(|e: barkit_extract::error::Error| e.to_string())
+}
+
+/// Parses built-in header format or header template
+fn parse_header_format(value: &str) -> Result<HeaderFormat, String> {
+    value
+        .parse()
+        .map_err(|e: barkit_extract::error::Error| e.to_string())
+}
+
+/// Parses error rate, which must be in the [0, 1) range
+fn parse_error_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value
+        .parse()
+        .map_err(|_| format!("{} is not a number", value))?;
+    if (0.0..1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("{} is not in the [0, 1) range", value))
+    }
 }
@@ -1,3 +1,9 @@
+use barkit_extract::notation::PatternSyntax;
+use barkit_extract::parse::{BarcodeCombiner, MatchPolicy};
+use barkit_extract::pattern::MaxError;
+use barkit_extract::preset::Preset;
+use barkit_extract::run::{IndexBarcodes, IndexFastq};
+use barkit_extract::whitelist::WhitelistFile;
 use clap::Parser;
 
 fn main() {
@@ -9,6 +15,7 @@ fn main() {
             output_fastqs,
             additional_params,
             patterns,
+            index_fastqs,
             compression,
         } => {
             let output_compression = barkit_extract::fastq::CompressionType::select(
@@ -17,22 +24,237 @@ fn main() {
                 &compression.mgz,
                 &compression.lz4,
             );
+            let preset = get_preset(patterns);
+            let (pattern1, pattern2) = match &preset {
+                Some(preset) => (preset.pattern1.clone(), preset.pattern2.clone()),
+                None => (
+                    get_patterns(
+                        &patterns.pattern1,
+                        &patterns.patterns_file1,
+                        patterns.pattern_syntax,
+                    ),
+                    get_patterns(
+                        &patterns.pattern2,
+                        &patterns.patterns_file2,
+                        patterns.pattern_syntax,
+                    ),
+                ),
+            };
             barkit_extract::run::run(
                 input_fastqs.fq1.to_string(),
                 input_fastqs.fq2.clone(),
-                patterns.pattern1.clone(),
-                patterns.pattern2.clone(),
+                pattern1,
+                pattern2,
+                get_index_fastqs(index_fastqs, additional_params),
                 output_fastqs.out_fq1.to_string(),
                 output_fastqs.out_fq2.clone(),
+                output_fastqs.unmatched_out1.clone(),
+                output_fastqs.unmatched_out2.clone(),
                 args.max_memory,
                 args.threads,
+                patterns.pattern_selection,
                 additional_params.rc_barcodes,
-                additional_params.skip_trimming,
-                additional_params.max_error,
+                additional_params.skip_trimming
+                    || preset.as_ref().is_some_and(|preset| preset.skip_trimming),
+                additional_params
+                    .whitelist
+                    .clone()
+                    .map(WhitelistFile::from)
+                    .or(preset.as_ref().and_then(|preset| preset.whitelist.clone())),
+                get_round_whitelists(additional_params, preset.as_ref()),
+                additional_params.pad_barcodes,
+                additional_params.reorient,
+                get_barcode_combiner(additional_params),
+                MatchPolicy::new(
+                    additional_params.match_requirement,
+                    &additional_params.optional_patterns,
+                ),
+                get_max_error(additional_params, additional_params.max_error1),
+                get_max_error(additional_params, additional_params.max_error2),
+                additional_params.window1.clone(),
+                additional_params.window2.clone(),
+                additional_params.indels,
+                additional_params.min_quality,
                 output_compression,
+                output_fastqs.bam,
+                output_fastqs.header_format.clone(),
                 args.quiet,
                 args.force,
             );
         }
+        barkit::Commands::Pattern {
+            pattern,
+            max_error,
+            max_error_rate,
+            indels,
+            sequences,
+        } => barkit_pattern::run::run(
+            pattern.to_string(),
+            select_max_error(*max_error, *max_error_rate),
+            *indels,
+            sequences.clone(),
+        ),
+        barkit::Commands::Demux {
+            fq1,
+            fq2,
+            index1,
+            index2,
+            sample_sheet,
+            out_dir,
+            inline_pattern,
+            max_mismatches,
+            dual_index,
+            compression,
+        } => barkit_demux::run::run(
+            fq1.to_string(),
+            fq2.clone(),
+            index1.clone(),
+            index2.clone(),
+            sample_sheet.to_string(),
+            out_dir.to_string(),
+            inline_pattern.clone(),
+            *max_mismatches,
+            *dual_index,
+            args.max_memory,
+            args.threads,
+            barkit_extract::fastq::CompressionType::select(
+                &compression.gz,
+                &compression.bgz,
+                &compression.mgz,
+                &compression.lz4,
+            ),
+            args.quiet,
+            args.force,
+        ),
+    }
+}
+
+/// Returns preset of barcode patterns selected by its name or built from the seqspec file
+fn get_preset(patterns: &barkit::PatternsGroup) -> Option<Preset> {
+    let preset = match (&patterns.preset, &patterns.seqspec) {
+        (Some(name), _) => Preset::find(name, patterns.presets_file.as_deref()),
+        (None, Some(seqspec)) => {
+            barkit_extract::seqspec::load_preset(seqspec, patterns.modality.as_deref())
+        }
+        (None, None) => return None,
+    };
+    Some(preset.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }))
+}
+
+/// Returns round whitelists provided by arguments or the preset
+fn get_round_whitelists(
+    additional_params: &barkit::AdditionalParamsGroup,
+    preset: Option<&Preset>,
+) -> Vec<WhitelistFile> {
+    if additional_params.round_whitelists.is_empty() {
+        preset
+            .map(|preset| preset.round_whitelists.clone())
+            .unwrap_or_default()
+    } else {
+        additional_params
+            .round_whitelists
+            .iter()
+            .cloned()
+            .map(WhitelistFile::from)
+            .collect()
+    }
+}
+
+/// Returns combiner of barcodes from both mates, if barcodes to combine are provided
+fn get_barcode_combiner(
+    additional_params: &barkit::AdditionalParamsGroup,
+) -> Option<BarcodeCombiner> {
+    (!additional_params.combine_barcodes.is_empty()).then(|| {
+        BarcodeCombiner::new(
+            additional_params.combine_barcodes.clone(),
+            additional_params.combine_order,
+            additional_params.combine_separator.clone(),
+        )
+    })
+}
+
+/// Returns index FASTQs with barcode patterns or tags of their reads
+fn get_index_fastqs(
+    index_group: &barkit::IndexGroup,
+    additional_params: &barkit::AdditionalParamsGroup,
+) -> Vec<IndexFastq> {
+    [
+        (
+            &index_group.index1,
+            &index_group.index_pattern1,
+            &index_group.index_tag1,
+            "I1",
+        ),
+        (
+            &index_group.index2,
+            &index_group.index_pattern2,
+            &index_group.index_tag2,
+            "I2",
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(path, patterns, tag, default_tag)| {
+        let barcodes = match (patterns.is_empty(), tag) {
+            (false, _) => IndexBarcodes::Patterns {
+                patterns: patterns.clone(),
+                max_error: get_max_error(additional_params, None),
+            },
+            (true, Some(tag)) => IndexBarcodes::Tag(tag.clone()),
+            (true, None) => IndexBarcodes::Tag(default_tag.to_string()),
+        };
+        path.as_ref().map(|path| IndexFastq {
+            path: path.clone(),
+            barcodes,
+        })
+    })
+    .collect()
+}
+
+/// Returns max error of adapters, the error rate takes precedence over the absolute number
+fn select_max_error(max_error: usize, max_error_rate: Option<f64>) -> MaxError {
+    match max_error_rate {
+        Some(rate) => MaxError::Rate(rate),
+        None => MaxError::Count(max_error),
+    }
+}
+
+/// Returns max error of adapters in the mate pattern
+fn get_max_error(
+    additional_params: &barkit::AdditionalParamsGroup,
+    mate_max_error: Option<usize>,
+) -> MaxError {
+    select_max_error(
+        mate_max_error.unwrap_or(additional_params.max_error),
+        additional_params.max_error_rate,
+    )
+}
+
+/// Returns alternative patterns of the mate provided by arguments and the patterns file,
+/// converted from the pattern syntax into regex
+fn get_patterns(
+    patterns: &[String],
+    patterns_file: &Option<String>,
+    pattern_syntax: PatternSyntax,
+) -> Vec<String> {
+    let mut patterns = patterns.to_vec();
+    if let Some(patterns_file) = patterns_file {
+        patterns.extend(
+            barkit_extract::pattern::read_patterns(patterns_file).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+        );
     }
+    patterns
+        .iter()
+        .map(|pattern| {
+            pattern_syntax.to_regex(pattern).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        })
+        .collect()
 }